@@ -0,0 +1,86 @@
+//! Runs arbitrary bytecode through the disassembler, the optimizer and the VM,
+//! none of which should ever panic. Whenever the original chunk runs to
+//! completion, the optimized one has to as well, and print exactly the same
+//! output.
+//!
+//! The first byte is the number of constants (up to 7), which are read as
+//! little-endian `f64`s from the bytes after it. Adding them to the pool
+//! emits an instruction which pushes each one, and the rest of the input is
+//! the code which follows.
+
+#![no_main]
+
+use std::{cell::RefCell, convert::TryInto, io, rc::Rc};
+
+use libfuzzer_sys::fuzz_target;
+use lox_rs::{Chunk, Error, OpCode, OptLevel, VM};
+
+/// Collects what the script prints, so that runs can be compared.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuf {
+	fn take(&self) -> Vec<u8> {
+		self.0.take()
+	}
+}
+
+impl io::Write for SharedBuf {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fuzz_target!(|data: &[u8]| {
+	let (&pool_len, rest) = match data.split_first() {
+		Some(split) => split,
+		None => return,
+	};
+	let pool_len = (pool_len % 8) as usize;
+	if rest.len() < pool_len * 8 {
+		return;
+	}
+	let (pool, code) = rest.split_at(pool_len * 8);
+
+	let mut chunk = Chunk::new();
+	for bytes in pool.chunks_exact(8) {
+		chunk.write_const(f64::from_le_bytes(bytes.try_into().unwrap()), 1);
+	}
+	// Make room for the code, then write over whatever was emitted
+	let start = chunk.len();
+	while chunk.len() < start + code.len() {
+		chunk.write_instr(OpCode::Return, 1);
+	}
+	chunk[start..].copy_from_slice(code);
+
+	let _ = format!("{:?}", chunk);
+
+	let output = SharedBuf::default();
+	let vm = VM::new();
+	vm.set_output(output.clone());
+	vm.enable_trace(io::sink());
+	vm.set_stack_limit(64);
+	let expected = vm.interpret(&chunk);
+	let expected_output = output.take();
+
+	let optimized = chunk.optimize(OptLevel::Peephole);
+	let _ = format!("{:?}", optimized);
+	let actual = vm.interpret(&optimized);
+	let actual_output = output.take();
+
+	// The optimizer can fold away pushes which would have overflowed the
+	// stack, but it mustn't change the result of a chunk which succeeds, or
+	// let any other failure succeed
+	match expected {
+		Err(Error::StackOverflow { .. }) => {}
+		Err(_) => assert!(actual.is_err(), "{:?}", optimized),
+		Ok(()) => {
+			assert_eq!(actual, Ok(()), "{:?}", optimized);
+			assert_eq!(actual_output, expected_output, "{:?}", optimized);
+		}
+	}
+});
@@ -21,11 +21,20 @@ impl fmt::Debug for OpCode {
 			Self::Constant   => "CONSTANT",
 			Self::Constant16 => "CONSTANT_16",
 			Self::Constant24 => "CONSTANT_24",
+			Self::Zero       => "ZERO",
+			Self::One        => "ONE",
+			Self::SmallInt   => "SMALL_INT",
+			Self::SmallInt16 => "SMALL_INT_16",
 			Self::Add        => "ADD",
 			Self::Subtract   => "SUBTRACT",
 			Self::Multiply   => "MULTIPLY",
 			Self::Divide     => "DIVIDE",
 			Self::Negate     => "NEGATE",
+			Self::AddConstant      => "ADD_CONSTANT",
+			Self::SubtractConstant => "SUBTRACT_CONSTANT",
+			Self::MultiplyConstant => "MULTIPLY_CONSTANT",
+			Self::DivideConstant   => "DIVIDE_CONSTANT",
+			Self::Print      => "PRINT",
 			Self::Return     => "RETURN",
 		};
 		debug::print_aligned(f, name)
@@ -0,0 +1,89 @@
+use crate::value::Value;
+
+use super::{Chunk, OpCode, Span};
+
+/// Bookkeeping for one of the most recently written instructions, used to
+/// spot constant operands as their operator is written.
+#[derive(Clone, Copy)]
+pub(super) struct Emitted {
+	pub offset: usize,
+	pub op: OpCode,
+	pub constant: Option<Constant>,
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct Constant {
+	pub value: Value,
+	/// The constant's slot in the pool, or `None` if it was written as an
+	/// immediate
+	pub handle: Option<usize>,
+	/// Whether the pool slot was added for this instruction, rather than
+	/// shared with an earlier one
+	pub fresh: bool,
+}
+
+impl Chunk {
+	/// Tries to fold `op` into the instructions just before it. Returns `false`
+	/// if `op` still needs to be written.
+	///
+	/// Only folds which give bit-for-bit the same result as the VM are done, so
+	/// NaN and -0 behave exactly as they would at runtime.
+	pub(super) fn try_fold(&mut self, op: OpCode, span: Span) -> bool {
+		use OpCode::*;
+
+		let value = match (op, &self.recent[..]) {
+			// -(-x) => x
+			(Negate, [.., Emitted { op: Negate, .. }]) => {
+				self.rewind();
+				return true;
+			}
+			// Same as the VM, which multiplies rather than flipping the sign bit,
+			// so that NaNs come out the same
+			#[allow(clippy::neg_multiply)]
+			(Negate, [.., Emitted { constant: Some(c), .. }]) => c.value * -1.,
+			(
+				Add | Subtract | Multiply | Divide,
+				[.., Emitted { constant: Some(lhs), .. }, Emitted { constant: Some(rhs), .. }],
+			) => {
+				let (lhs, rhs) = (lhs.value, rhs.value);
+				self.rewind();
+				match op {
+					Add => lhs + rhs,
+					Subtract => lhs - rhs,
+					Multiply => lhs * rhs,
+					_ => lhs / rhs,
+				}
+			}
+			_ => return false,
+		};
+
+		self.rewind();
+		self.write_number(value, span);
+		true
+	}
+
+	/// Removes the last instruction in the chunk, and releases its constant if
+	/// nothing else refers to it.
+	pub(super) fn rewind(&mut self) {
+		let instr = match self.recent.pop() {
+			Some(instr) => instr,
+			None => return,
+		};
+		self.data.truncate(instr.offset);
+		self.lines.truncate(instr.offset);
+
+		if let Some(Constant {
+			value,
+			handle: Some(handle),
+			fresh: true,
+		}) = instr.constant
+		{
+			if handle + 1 == self.constants.len() {
+				self.constants.truncate(handle);
+				if let Some(dedup) = &mut self.dedup {
+					dedup.remove(&value.to_bits());
+				}
+			}
+		}
+	}
+}
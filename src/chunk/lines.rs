@@ -1,62 +1,263 @@
+use std::fmt;
+
 use crate::vector::{vector, Vector};
 
-#[derive(Clone, Copy)]
-pub struct LineStart {
+/// The region of source code an instruction was generated from: a line, plus
+/// a half-open range of byte columns within that line.
+///
+/// Columns are 0-based. A span with `end <= start` has no column information
+/// and covers the whole line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+	/// The 1-based line number
 	pub line: usize,
-	pub offset: usize,
+	/// The first column in the span
+	pub start: usize,
+	/// The column just past the end of the span
+	pub end: usize,
+}
+
+impl Span {
+	/// Creates a span covering `start..end` of `line`.
+	pub fn new(line: usize, start: usize, end: usize) -> Self {
+		Self { line, start, end }
+	}
+
+	/// Whether the span covers part of its line, rather than all of it.
+	pub fn has_columns(&self) -> bool {
+		self.end > self.start
+	}
+}
+
+impl From<usize> for Span {
+	fn from(line: usize) -> Self {
+		Self::new(line, 0, 0)
+	}
+}
+
+impl fmt::Display for Span {
+	/// Formats as `line` or `line:start-end`, with 1-based columns.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.has_columns() {
+			write!(f, "{}:{}-{}", self.line, self.start + 1, self.end)
+		} else {
+			write!(f, "{}", self.line)
+		}
+	}
 }
 
+/// Maps bytecode offsets to source spans.
+///
+/// Like CPython's `co_lnotab`, the table is a byte string of delta-encoded
+/// records, one for each run of bytes which share a span:
+///
+/// ```text
+/// varint(offset - prev offset)  zigzag(line - prev line)  varint(start)  varint(end - start)
+/// ```
+///
+/// Sequential lookups go through a [`Cursor`], which decodes each record once.
+/// Every [`Lines::CHECKPOINT_INTERVAL`] records, the decoder state is saved so
+/// that random lookups only need to decode a bounded number of records.
 pub struct Lines {
-	inner: Vector<LineStart>,
+	data: Vector<u8>,
+	checkpoints: Vector<Cursor>,
+	count: usize,
+	tail: Option<(usize, Span)>,
+}
+
+/// Decoder state for [`Lines`]: the run containing the last sought offset,
+/// and the position of the record after it.
+///
+/// A cursor is only meaningful for the table that created it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cursor {
+	/// Byte index of the next record to decode
+	pos: usize,
+	/// Offset where the current run starts, or `None` if no record has been
+	/// decoded yet
+	offset: Option<usize>,
+	span: Span,
+	/// Offset where the next run starts
+	next_offset: Option<usize>,
 }
 
 impl Lines {
-	pub fn new() -> Self {
-		Self { inner: vector![] }
+	/// How many records there are between saved decoder states.
+	pub const CHECKPOINT_INTERVAL: usize = 32;
+
+	pub(crate) fn new() -> Self {
+		Self {
+			data: vector![],
+			checkpoints: vector![],
+			count: 0,
+			tail: None,
+		}
+	}
+
+	pub(crate) fn add_byte(&mut self, span: Span, offset: usize) {
+		let (prev_offset, prev_line) = match self.tail {
+			Some((_, tail)) if tail == span => return,
+			Some((prev_offset, tail)) => (prev_offset, tail.line),
+			None => (0, 0),
+		};
+		assert!(offset >= prev_offset, "Lines must be added in offset order");
+
+		if self.count.is_multiple_of(Self::CHECKPOINT_INTERVAL) {
+			self.checkpoints.push(Cursor {
+				pos: self.data.len(),
+				offset: self.tail.map(|(offset, _)| offset),
+				span: self.tail.map(|(_, span)| span).unwrap_or_default(),
+				next_offset: Some(offset),
+			});
+		}
+
+		write_varint(&mut self.data, (offset - prev_offset) as u64);
+		write_varint(&mut self.data, zigzag(span.line as i64 - prev_line as i64));
+		write_varint(&mut self.data, span.start as u64);
+		write_varint(&mut self.data, span.end.saturating_sub(span.start) as u64);
+
+		self.count += 1;
+		self.tail = Some((offset, span));
 	}
 
-	pub fn add_byte(&mut self, line: usize, offset: usize) {
-		let tail = self.tail();
-		if tail.is_none() || tail.unwrap().line != line {
-			self.inner.push(LineStart { line, offset });
+	/// Removes the entries for every offset from `len` onwards, e.g. when the
+	/// tail of a chunk is rewritten.
+	pub(crate) fn truncate(&mut self, len: usize) {
+		let kept = self
+			.checkpoints
+			.partition_point(|it| it.next_offset.is_some_and(|start| start < len));
+		self.checkpoints.truncate(kept);
+
+		// Replay the records after the last checkpoint we're keeping
+		let mut cursor = self.checkpoints.last().copied().unwrap_or_default();
+		let mut count = kept.saturating_sub(1) * Self::CHECKPOINT_INTERVAL;
+		while cursor.next_offset.is_some_and(|start| start < len) {
+			self.decode_next(&mut cursor);
+			count += 1;
 		}
+
+		self.data.truncate(cursor.pos);
+		self.count = count;
+		self.tail = cursor.offset.map(|offset| (offset, cursor.span));
 	}
 
+	/// Returns the line of the instruction at `offset`, or 0 if the table has
+	/// no entry covering it.
 	pub fn find_line(&self, offset: usize) -> usize {
-		// Binary search for the last LineStart whose offset <= the given param
-		let mut start = 0;
-		let mut end = self.last_idx();
-
-		loop {
-			let mid = (start + end) / 2;
-			let line_start = &self.inner[mid];
-
-			if offset < line_start.offset {
-				// Needle is in first half of the haystack
-				end = mid - 1;
-			} else if mid == self.last_idx() || offset < self.inner[mid + 1].offset {
-				// Found it
-				break line_start.line;
-			} else {
-				// Needle is in second half of the haystack
-				start = mid + 1;
+		self.find_span(offset).line
+	}
+
+	/// Returns the span of the instruction at `offset`, or an empty span on
+	/// line 0 if the table has no entry covering it.
+	pub fn find_span(&self, offset: usize) -> Span {
+		let mut cursor = self.checkpoint_before(offset);
+		self.seek(&mut cursor, offset)
+	}
+
+	/// A cursor positioned before the first record.
+	pub fn cursor(&self) -> Cursor {
+		self.checkpoints.first().copied().unwrap_or_default()
+	}
+
+	/// Advances `cursor` to the run containing `offset` and returns its span.
+	///
+	/// This is amortized O(1) when offsets are visited in increasing order.
+	/// Seeking backwards restarts from the nearest checkpoint.
+	pub fn seek(&self, cursor: &mut Cursor, offset: usize) -> Span {
+		if cursor.offset.is_none_or(|start| offset < start) {
+			*cursor = self.checkpoint_before(offset);
+		}
+
+		while let Some(next_offset) = cursor.next_offset {
+			if offset < next_offset {
+				break;
 			}
+			self.decode_next(cursor);
+		}
+
+		match cursor.offset {
+			Some(start) if offset >= start => cursor.span,
+			_ => Span::default(),
 		}
 	}
 
-	fn last_idx(&self) -> usize {
-		if self.inner.is_empty() {
-			0
-		} else {
-			self.inner.len() - 1
+	fn checkpoint_before(&self, offset: usize) -> Cursor {
+		// Binary search for the last checkpoint whose first record starts at
+		// or before `offset`
+		let idx = self
+			.checkpoints
+			.partition_point(|it| it.next_offset.is_some_and(|start| start <= offset));
+
+		match idx {
+			0 => self.cursor(),
+			_ => self.checkpoints[idx - 1],
 		}
 	}
 
-	fn tail(&self) -> Option<&LineStart> {
-		if self.inner.is_empty() {
-			None
-		} else {
-			Some(&self.inner[self.last_idx()])
+	/// Decodes the record at `cursor.pos`, making it the cursor's current run.
+	fn decode_next(&self, cursor: &mut Cursor) {
+		let mut pos = cursor.pos;
+		let (offset_delta, line_delta, start, width) = match (
+			read_varint(&self.data, &mut pos),
+			read_varint(&self.data, &mut pos),
+			read_varint(&self.data, &mut pos),
+			read_varint(&self.data, &mut pos),
+		) {
+			(Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+			_ => {
+				cursor.next_offset = None;
+				return;
+			}
+		};
+
+		let offset = cursor.offset.unwrap_or(0) + offset_delta as usize;
+		let line = (cursor.span.line as i64 + unzigzag(line_delta)) as usize;
+		let start = start as usize;
+
+		cursor.pos = pos;
+		cursor.offset = Some(offset);
+		cursor.span = Span::new(line, start, start + width as usize);
+		cursor.next_offset = self.peek_offset(pos).map(|delta| offset + delta);
+	}
+
+	fn peek_offset(&self, mut pos: usize) -> Option<usize> {
+		read_varint(&self.data, &mut pos).map(|delta| delta as usize)
+	}
+}
+
+fn write_varint(buf: &mut Vector<u8>, mut n: u64) {
+	loop {
+		let byte = (n & 0x7f) as u8;
+		n >>= 7;
+		if n == 0 {
+			buf.push(byte);
+			break;
+		}
+		buf.push(byte | 0x80);
+	}
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+	let mut n = 0;
+	let mut shift = 0;
+	loop {
+		let byte = *buf.get(*pos)?;
+		*pos += 1;
+		n |= ((byte & 0x7f) as u64) << shift;
+		if byte & 0x80 == 0 {
+			break Some(n);
+		}
+		shift += 7;
+		if shift >= 64 {
+			break None;
 		}
 	}
 }
+
+fn zigzag(n: i64) -> u64 {
+	((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+	((n >> 1) as i64) ^ -((n & 1) as i64)
+}
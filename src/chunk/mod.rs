@@ -1,14 +1,16 @@
+//! Bytecode chunks and the instructions they hold.
+
 use std::{
+	collections::HashMap,
 	convert::TryFrom,
 	ops::{Deref, DerefMut},
 };
 
-use num_derive::FromPrimitive;
-
 mod debug;
-mod into_iter;
+mod fold;
 mod join_bytes;
 mod lines;
+mod peephole;
 
 #[cfg(test)]
 mod tests;
@@ -18,23 +20,63 @@ use crate::{
 	vector::{vector, Vector},
 };
 
-pub use self::{into_iter::Consumable, join_bytes::JoinBytes, lines::Lines};
+pub(crate) use self::join_bytes::JoinBytes;
+pub use self::{
+	lines::{Cursor, Lines, Span},
+	peephole::OptLevel,
+};
 
-#[derive(FromPrimitive, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// A bytecode instruction. Operands follow the opcode byte, and multi-byte
+/// operands are big-endian.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 #[rustfmt::skip]
 pub enum OpCode {
+	/// Pushes a constant, using a 1-byte handle into the pool
 	Constant   = 0x00,
+	/// Pushes a constant, using a 2-byte handle
 	Constant16 = 0x01,
+	/// Pushes a constant, using a 3-byte handle
 	Constant24 = 0x02,
+	/// Pushes `0`
+	Zero       = 0x03,
+	/// Pushes `1`
+	One        = 0x04,
+	/// Pushes its 1-byte signed operand
+	SmallInt   = 0x05,
+	/// Pushes its 2-byte signed operand
+	SmallInt16 = 0x06,
+	/// Pops two values and pushes their sum
 	Add        = 0x10,
+	/// Pops two values and pushes the first minus the second
 	Subtract   = 0x11,
+	/// Pops two values and pushes their product
 	Multiply   = 0x12,
+	/// Pops two values and pushes the first divided by the second
 	Divide     = 0x13,
+	/// Negates the value on top of the stack
 	Negate     = 0x14,
+
+	// Superinstructions: an arithmetic op whose right operand is a constant,
+	// with a 1-byte handle
+
+	/// Adds a constant to the value on top of the stack
+	AddConstant      = 0x20,
+	/// Subtracts a constant from the value on top of the stack
+	SubtractConstant = 0x21,
+	/// Multiplies the value on top of the stack by a constant
+	MultiplyConstant = 0x22,
+	/// Divides the value on top of the stack by a constant
+	DivideConstant   = 0x23,
+
+	/// Pops a value and writes it to the VM's output
+	Print      = 0x30,
+	/// Pops the value on top of the stack
 	Return     = 0xFF,
 }
 
+/// The error for a byte which isn't a valid [`OpCode`], with a description of
+/// the byte.
 pub struct OpCodeError(pub String);
 
 impl TryFrom<u8> for OpCode {
@@ -45,72 +87,225 @@ impl TryFrom<u8> for OpCode {
 			0x00 => Ok(OpCode::Constant),
 			0x01 => Ok(OpCode::Constant16),
 			0x02 => Ok(OpCode::Constant24),
+			0x03 => Ok(OpCode::Zero),
+			0x04 => Ok(OpCode::One),
+			0x05 => Ok(OpCode::SmallInt),
+			0x06 => Ok(OpCode::SmallInt16),
 			0x10 => Ok(OpCode::Add),
 			0x11 => Ok(OpCode::Subtract),
 			0x12 => Ok(OpCode::Multiply),
 			0x13 => Ok(OpCode::Divide),
 			0x14 => Ok(OpCode::Negate),
+			0x20 => Ok(OpCode::AddConstant),
+			0x21 => Ok(OpCode::SubtractConstant),
+			0x22 => Ok(OpCode::MultiplyConstant),
+			0x23 => Ok(OpCode::DivideConstant),
+			0x30 => Ok(OpCode::Print),
 			0xFF => Ok(OpCode::Return),
 			_ => Err(OpCodeError(format!("UNKNOWN: {:#04x}", byte))),
 		}
 	}
 }
 
+/// A unit of bytecode, along with the constants it refers to and the source
+/// spans its instructions were generated from.
+///
+/// The code itself can be read through `Deref<Target = [u8]>`.
 pub struct Chunk {
 	data: Vector<u8>,
 	constants: Vector<Value>,
 	lines: Lines,
+	/// Constant pool handles, keyed by the bits of their values. `None` if
+	/// deduplication is disabled.
+	dedup: Option<HashMap<u64, usize>>,
+	fold: bool,
+	/// The instructions written since the last one that can't be folded away
+	recent: Vector<fold::Emitted>,
 }
 
 impl Chunk {
+	/// Creates an empty chunk, with constant deduplication on and folding off.
 	pub fn new() -> Self {
 		Self {
 			data: vector![],
 			constants: vector![],
 			lines: Lines::new(),
+			dedup: Some(HashMap::new()),
+			fold: false,
+			recent: vector![],
 		}
 	}
 
-	pub fn write_instr(&mut self, op: OpCode, line: usize) {
-		self.write(op as u8, line);
+	/// Turns constant deduplication on or off. It's on by default, so that
+	/// writing the same value twice reuses its slot in the constant pool.
+	///
+	/// Only constants written while deduplication is on can be reused.
+	pub fn set_dedup_constants(&mut self, enabled: bool) {
+		match (enabled, &self.dedup) {
+			(true, None) => self.dedup = Some(HashMap::new()),
+			(false, Some(_)) => self.dedup = None,
+			_ => {}
+		}
 	}
 
-	pub fn write_const(&mut self, value: Value, line: usize) {
+	/// Turns constant folding on or off. It's off by default.
+	///
+	/// While it's on, arithmetic on constants that were just written is done
+	/// up front: writing `Constant 1, Constant 2, Add` leaves a single
+	/// instruction which pushes `3`, and `Negate, Negate` cancels out.
+	///
+	/// Folding looks back at the instructions written before the operator, so
+	/// it has to be suspended around anything that can be jumped to.
+	pub fn set_fold_constants(&mut self, enabled: bool) {
+		self.fold = enabled;
+	}
+
+	/// The constant pool, indexed by the handles in the code.
+	pub fn constants(&self) -> &[Value] {
+		&self.constants
+	}
+
+	/// Maps bytecode offsets to the source spans they were generated from.
+	pub fn lines(&self) -> &Lines {
+		&self.lines
+	}
+
+	/// Appends an instruction with no operands. `span` can be a full [`Span`]
+	/// or just a line number.
+	pub fn write_instr<S: Into<Span>>(&mut self, op: OpCode, span: S) {
+		let span = span.into();
+		if self.fold && self.try_fold(op, span) {
+			return;
+		}
+
+		self.remember(op, None);
+		self.write(op as u8, span);
+	}
+
+	/// Adds `value` to the constant pool and appends an instruction which
+	/// pushes it, with the narrowest handle that fits.
+	pub fn write_const<S: Into<Span>>(&mut self, value: Value, span: S) {
+		let span = span.into();
+		let pool_len = self.constants.len();
 		let handle = self.add_constant(value);
 
-		match handle {
-			0..=255 => {
-				self.write(OpCode::Constant as u8, line);
-				self.write(handle as u8, line);
-			}
-			256..=65_535 => {
-				self.write(OpCode::Constant16 as u8, line);
+		let op = match handle {
+			0..=255 => OpCode::Constant,
+			256..=65_535 => OpCode::Constant16,
+			_ => OpCode::Constant24,
+		};
+		self.remember(
+			op,
+			Some(fold::Constant {
+				value,
+				handle: Some(handle),
+				fresh: self.constants.len() > pool_len,
+			}),
+		);
+
+		self.write(op as u8, span);
+		match op {
+			OpCode::Constant => self.write(handle as u8, span),
+			OpCode::Constant16 => {
 				let bytes = (handle as u16).to_be_bytes();
-				self.extend(&bytes, line);
+				self.extend(&bytes, span);
 			}
 			_ => {
-				self.write(OpCode::Constant24 as u8, line);
 				let [_, b, c, d] = (handle as u32).to_be_bytes();
-				self.extend(&[b, c, d], line);
+				self.extend(&[b, c, d], span);
 			}
 		}
 	}
 
-	fn write(&mut self, byte: u8, line: usize) {
-		self.data.push(byte);
-		self.lines.add_byte(line, self.data.len() - 1);
+	/// Appends a number literal. Integers which fit in an `i16` are written as
+	/// immediates, so they don't take up a slot in the constant pool; anything
+	/// else goes through [`Chunk::write_const`].
+	pub fn write_number<S: Into<Span>>(&mut self, value: Value, span: S) {
+		let span = span.into();
+		let int = match small_int(value) {
+			Some(int) => int,
+			None => return self.write_const(value, span),
+		};
+
+		let op = match int {
+			0 => OpCode::Zero,
+			1 => OpCode::One,
+			-128..=127 => OpCode::SmallInt,
+			_ => OpCode::SmallInt16,
+		};
+		self.remember(
+			op,
+			Some(fold::Constant {
+				value,
+				handle: None,
+				fresh: false,
+			}),
+		);
+
+		self.write(op as u8, span);
+		match op {
+			OpCode::SmallInt => self.write(int as i8 as u8, span),
+			OpCode::SmallInt16 => self.extend(&int.to_be_bytes(), span),
+			_ => {}
+		}
 	}
 
-	fn extend(&mut self, bytes: &[u8], line: usize) {
-		self.lines.add_byte(line, self.data.len());
-		for byte in bytes.iter() {
-			self.data.push(*byte);
+	fn remember(&mut self, op: OpCode, constant: Option<fold::Constant>) {
+		// Nothing before an instruction that isn't a constant can be folded
+		// unless that instruction is removed first, which only happens to the
+		// most recent one
+		if constant.is_none() {
+			self.recent.clear();
 		}
+		self.recent.push(fold::Emitted {
+			offset: self.data.len(),
+			op,
+			constant,
+		});
+	}
+
+	fn write(&mut self, byte: u8, span: Span) {
+		self.data.push(byte);
+		self.lines.add_byte(span, self.data.len() - 1);
+	}
+
+	fn extend(&mut self, bytes: &[u8], span: Span) {
+		self.lines.add_byte(span, self.data.len());
+		self.data.extend_from_slice(bytes);
 	}
 
 	fn add_constant(&mut self, value: Value) -> usize {
+		// Compare bitwise so that 0 and -0 get separate slots, while NaNs can
+		// still share one
+		let key = value.to_bits();
+		if let Some(&handle) = self.dedup.as_ref().and_then(|dedup| dedup.get(&key)) {
+			return handle;
+		}
+
 		self.constants.push(value);
-		self.constants.len() - 1
+		let handle = self.constants.len() - 1;
+
+		if let Some(dedup) = &mut self.dedup {
+			dedup.insert(key, handle);
+		}
+		handle
+	}
+}
+
+impl Default for Chunk {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// `value` as an `i16`, if it can be converted and back without changing its
+/// bits. (-0 can't.)
+fn small_int(value: Value) -> Option<i16> {
+	let int = value as i16;
+	if (int as Value).to_bits() == value.to_bits() {
+		Some(int)
+	} else {
+		None
 	}
 }
 
@@ -118,12 +313,12 @@ impl Deref for Chunk {
 	type Target = [u8];
 
 	fn deref(&self) -> &Self::Target {
-		&*self.data
+		&self.data
 	}
 }
 
 impl DerefMut for Chunk {
 	fn deref_mut(&mut self) -> &mut Self::Target {
-		&mut *self.data
+		&mut self.data
 	}
 }
@@ -0,0 +1,187 @@
+use std::convert::TryFrom;
+
+use crate::{
+	value::Value,
+	vector::{vector, Vector},
+};
+
+use super::{fold::Emitted, Chunk, OpCode, Span};
+
+/// How much work to put into a chunk once it's been written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+	/// Leave the bytecode exactly as it was written
+	#[default]
+	None,
+	/// Fold constants, remove instructions which can't change the result, and
+	/// fuse common instruction sequences into superinstructions
+	Peephole,
+}
+
+/// A decoded instruction, with its constant operand resolved.
+struct Instr {
+	op: OpCode,
+	value: Option<Value>,
+	span: Span,
+}
+
+impl Chunk {
+	/// Rewrites the finished chunk at the given optimization level.
+	///
+	/// The chunk is decoded and written out again, so the line table and
+	/// constant pool are rebuilt to match. Chunks which don't decode cleanly,
+	/// or which would underflow the stack, are returned as they are, so that
+	/// the VM still reports the error.
+	pub fn optimize(self, level: OptLevel) -> Chunk {
+		if level == OptLevel::None {
+			return self;
+		}
+		let instrs = match self.decode() {
+			Some(instrs) => instrs,
+			None => return self,
+		};
+
+		let mut result = Chunk::new();
+		result.set_dedup_constants(self.dedup.is_some());
+		result.set_fold_constants(true);
+
+		for instr in instrs {
+			match (instr.op, instr.value) {
+				(_, Some(value)) => result.write_number(value, instr.span),
+				(op, _) if result.is_identity_operand(op) => result.rewind(),
+				(op, _) => result.write_fused(op, instr.span),
+			}
+		}
+
+		result.set_fold_constants(self.fold);
+		result
+	}
+
+	/// Whether the last instruction written is a constant which `op` would
+	/// leave its other operand unchanged with, e.g. `x * 1`.
+	///
+	/// Adding `0` isn't an identity, since `-0 + 0` is `0`, but adding `-0` is.
+	fn is_identity_operand(&self, op: OpCode) -> bool {
+		let value = match self.recent.last().and_then(|it| it.constant) {
+			Some(c) => c.value,
+			None => return false,
+		};
+		match op {
+			OpCode::Add => value.to_bits() == (-0f64).to_bits(),
+			OpCode::Subtract => value.to_bits() == 0f64.to_bits(),
+			OpCode::Multiply | OpCode::Divide => value == 1.,
+			_ => false,
+		}
+	}
+
+	/// Writes `op`, fusing it with the constant before it if there's a
+	/// superinstruction for the pair.
+	fn write_fused(&mut self, op: OpCode, span: Span) {
+		let fused = match op {
+			OpCode::Add => OpCode::AddConstant,
+			OpCode::Subtract => OpCode::SubtractConstant,
+			OpCode::Multiply => OpCode::MultiplyConstant,
+			OpCode::Divide => OpCode::DivideConstant,
+			_ => return self.write_instr(op, span),
+		};
+		// Folding takes priority, since it removes the instruction entirely
+		if self.try_fold(op, span) {
+			return;
+		}
+
+		let value = match self.recent.last() {
+			Some(Emitted {
+				op: OpCode::Constant,
+				constant: Some(c),
+				..
+			}) => c.value,
+			_ => return self.write_instr(op, span),
+		};
+		self.rewind();
+
+		// Rewinding may have released the constant, so add it back
+		let handle = self.add_constant(value);
+		self.write_instr(fused, span);
+		self.write(handle as u8, span);
+	}
+
+	/// Returns `None` if any instruction is malformed, or would run with
+	/// fewer values on the stack than it pops, since folding and removing
+	/// instructions could hide the error.
+	fn decode(&self) -> Option<Vector<Instr>> {
+		let mut instrs = vector![];
+		let mut cursor = self.lines.cursor();
+		let mut offset = 0;
+		let mut depth = 0usize;
+
+		while offset < self.len() {
+			let op = OpCode::try_from(self[offset]).ok()?;
+			let (pops, pushes) = stack_effect(op);
+			depth = depth.checked_sub(pops)? + pushes;
+			let span = self.lines.seek(&mut cursor, offset);
+			let operand_len = match op {
+				OpCode::Constant
+				| OpCode::SmallInt
+				| OpCode::AddConstant
+				| OpCode::SubtractConstant
+				| OpCode::MultiplyConstant
+				| OpCode::DivideConstant => 1,
+				OpCode::Constant16 | OpCode::SmallInt16 => 2,
+				OpCode::Constant24 => 3,
+				_ => 0,
+			};
+
+			let operand = if operand_len > 0 {
+				let bytes = self.get(offset + 1..offset + 1 + operand_len)?;
+				Some(bytes.iter().fold(0, |acc, byte| acc << 8 | *byte as usize))
+			} else {
+				None
+			};
+			let value = match (op, operand) {
+				(OpCode::Zero, _) => Some(0.),
+				(OpCode::One, _) => Some(1.),
+				(OpCode::SmallInt, Some(byte)) => Some(byte as u8 as i8 as Value),
+				(OpCode::SmallInt16, Some(bytes)) => Some(bytes as u16 as i16 as Value),
+				(_, Some(handle)) => Some(*self.constants.get(handle)?),
+				(_, None) => None,
+			};
+
+			// Split superinstructions back up, so that chunks can be optimized
+			// more than once
+			match op {
+				OpCode::AddConstant => {
+					instrs.push(Instr { op: OpCode::Constant, value, span });
+					instrs.push(Instr { op: OpCode::Add, value: None, span });
+				}
+				OpCode::SubtractConstant => {
+					instrs.push(Instr { op: OpCode::Constant, value, span });
+					instrs.push(Instr { op: OpCode::Subtract, value: None, span });
+				}
+				OpCode::MultiplyConstant => {
+					instrs.push(Instr { op: OpCode::Constant, value, span });
+					instrs.push(Instr { op: OpCode::Multiply, value: None, span });
+				}
+				OpCode::DivideConstant => {
+					instrs.push(Instr { op: OpCode::Constant, value, span });
+					instrs.push(Instr { op: OpCode::Divide, value: None, span });
+				}
+				_ => instrs.push(Instr { op, value, span }),
+			}
+			offset += 1 + operand_len;
+		}
+
+		Some(instrs)
+	}
+}
+
+/// How many values `op` pops off the stack, and how many it pushes.
+fn stack_effect(op: OpCode) -> (usize, usize) {
+	use OpCode::*;
+
+	match op {
+		Constant | Constant16 | Constant24 | Zero | One | SmallInt | SmallInt16 => (0, 1),
+		Add | Subtract | Multiply | Divide => (2, 1),
+		AddConstant | SubtractConstant | MultiplyConstant | DivideConstant | Negate => (1, 1),
+		Print | Return => (1, 0),
+	}
+}
@@ -12,10 +12,10 @@ fn it_works() {
 
 	// eprintln!("{:?}", chunk);
 	let expected = r#"
-0000   123 CONSTANT          [0] '1.2'
+0000   123 CONSTANT           [0] '1.2'
 0002     | RETURN
-0003   124 CONSTANT          [1] '420'
-0005     | CONSTANT          [2] '69'
+0003   124 CONSTANT           [1] '420'
+0005     | CONSTANT           [2] '69'
 0007     | RETURN
 "#;
 	assert_eq!(&format!("\n{:?}\n", chunk), expected);
@@ -54,3 +54,466 @@ fn it_can_store_more_than_65535_constants() {
 	assert_eq!(chunk.constants.len(), 65_546);
 	assert!((chunk.constants[65_545] - 65_545.).abs() < f64::EPSILON);
 }
+
+#[test]
+fn it_tracks_column_spans() {
+	let mut chunk = Chunk::new();
+
+	// -(1.2 + 3.4)
+	chunk.write_const(1.2, Span::new(1, 2, 5));
+	chunk.write_const(3.4, Span::new(1, 8, 11));
+	chunk.write_instr(OpCode::Add, Span::new(1, 2, 11));
+	chunk.write_instr(OpCode::Negate, Span::new(1, 0, 12));
+	chunk.write_instr(OpCode::Return, 2);
+
+	let lines = &chunk.lines;
+	assert_eq!(lines.find_span(0), Span::new(1, 2, 5));
+	assert_eq!(lines.find_span(1), Span::new(1, 2, 5));
+	assert_eq!(lines.find_span(2), Span::new(1, 8, 11));
+	assert_eq!(lines.find_span(4), Span::new(1, 2, 11));
+	assert_eq!(lines.find_span(5), Span::new(1, 0, 12));
+	assert_eq!(lines.find_span(6), Span::from(2));
+
+	assert_eq!(lines.find_line(5), 1);
+	assert_eq!(lines.find_line(6), 2);
+
+	assert_eq!(Span::new(1, 2, 5).to_string(), "1:3-5");
+	assert_eq!(Span::from(2).to_string(), "2");
+}
+
+#[test]
+fn line_lookups_handle_empty_tables_and_leading_offsets() {
+	let mut lines = Lines::new();
+	assert_eq!(lines.find_line(0), 0);
+	assert_eq!(lines.find_span(42), Span::default());
+
+	lines.add_byte(Span::from(7), 3);
+	assert_eq!(lines.find_line(0), 0);
+	assert_eq!(lines.find_line(2), 0);
+	assert_eq!(lines.find_line(3), 7);
+	assert_eq!(lines.find_line(100), 7);
+}
+
+#[test]
+fn line_cursor_matches_random_lookups() {
+	let mut chunk = Chunk::new();
+	let mut expected = vec![];
+
+	// Enough runs to span several checkpoints, with lines going backwards
+	// every so often
+	for i in 0..500usize {
+		let line = 1000 + i / 3 - (i % 7) * 2;
+		let span = Span::new(line, i % 5, i % 5 + i % 4);
+
+		chunk.write_const(i as f64, span);
+		expected.resize(chunk.len(), span);
+		chunk.write_instr(OpCode::Negate, line);
+		expected.resize(chunk.len(), Span::from(line));
+	}
+
+	let lines = &chunk.lines;
+	let mut cursor = lines.cursor();
+	for (offset, span) in expected.iter().enumerate() {
+		assert_eq!(lines.seek(&mut cursor, offset), *span);
+		assert_eq!(lines.find_span(offset), *span);
+	}
+
+	// Seeking backwards restarts from a checkpoint
+	assert_eq!(lines.seek(&mut cursor, 5), expected[5]);
+}
+
+#[test]
+fn it_deduplicates_constants() {
+	let mut chunk = Chunk::new();
+	for _ in 0..100 {
+		chunk.write_const(1., 1);
+	}
+	chunk.write_const(2., 1);
+	chunk.write_const(1., 1);
+
+	assert_eq!(chunk.constants.len(), 2);
+	let constant = OpCode::Constant as u8;
+	assert_eq!(&chunk[..4], &[constant, 0, constant, 0]);
+	assert_eq!(&chunk[chunk.len() - 4..], &[constant, 1, constant, 0]);
+}
+
+#[test]
+fn constant_dedup_is_bitwise() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(0., 1);
+	chunk.write_const(-0., 1);
+	chunk.write_const(f64::NAN, 1);
+	chunk.write_const(f64::NAN, 1);
+	chunk.write_const(-f64::NAN, 1);
+
+	assert_eq!(chunk.constants.len(), 4);
+	assert_eq!(chunk.constants[0].to_bits(), 0f64.to_bits());
+	assert_eq!(chunk.constants[1].to_bits(), (-0f64).to_bits());
+	assert_eq!(chunk.constants[2].to_bits(), f64::NAN.to_bits());
+	assert_eq!(chunk.constants[3].to_bits(), (-f64::NAN).to_bits());
+}
+
+#[test]
+fn constant_dedup_can_be_disabled() {
+	let mut chunk = Chunk::new();
+	chunk.set_dedup_constants(false);
+	chunk.write_const(1., 1);
+	chunk.write_const(1., 1);
+	assert_eq!(chunk.constants.len(), 2);
+
+	chunk.set_dedup_constants(true);
+	chunk.write_const(1., 1);
+	chunk.write_const(1., 1);
+	assert_eq!(chunk.constants.len(), 3);
+}
+
+#[test]
+fn it_folds_constant_arithmetic() {
+	let mut chunk = Chunk::new();
+	chunk.set_fold_constants(true);
+	chunk.write_const(1.2, 123);
+	chunk.write_const(3.4, 123);
+	chunk.write_instr(OpCode::Add, 123);
+	chunk.write_const(5.6, 123);
+	chunk.write_instr(OpCode::Divide, 123);
+	chunk.write_instr(OpCode::Negate, 123);
+	chunk.write_instr(OpCode::Return, 124);
+
+	let expected = r#"
+0000   123 CONSTANT           [0] '-0.8214285714285714'
+0002   124 RETURN
+"#;
+	assert_eq!(&format!("\n{:?}\n", chunk), expected);
+	assert_eq!(chunk.constants.len(), 1);
+	assert_eq!(chunk.constants[0], -((1.2 + 3.4) / 5.6));
+}
+
+#[test]
+fn it_folds_right_nested_operands() {
+	// 1 - (2 * 3)
+	let mut chunk = Chunk::new();
+	chunk.set_fold_constants(true);
+	chunk.write_const(1., 1);
+	chunk.write_const(2., 1);
+	chunk.write_const(3., 1);
+	chunk.write_instr(OpCode::Multiply, 1);
+	chunk.write_instr(OpCode::Subtract, 1);
+
+	assert_eq!(&chunk[..], &[OpCode::SmallInt as u8, -5i8 as u8]);
+	assert!(chunk.constants.is_empty());
+}
+
+#[test]
+fn double_negation_cancels_out() {
+	let mut chunk = Chunk::new();
+	chunk.set_fold_constants(true);
+	chunk.write_const(1., 1);
+	chunk.write_const(2., 1);
+	chunk.write_instr(OpCode::Add, 1);
+	chunk.write_instr(OpCode::Return, 1);
+
+	// The operand isn't a constant, so only the negations can go
+	let before = chunk.len();
+	chunk.write_instr(OpCode::Negate, 2);
+	chunk.write_instr(OpCode::Negate, 2);
+	assert_eq!(chunk.len(), before);
+	assert_eq!(chunk.lines.find_line(before - 1), 1);
+
+	chunk.write_instr(OpCode::Negate, 3);
+	assert_eq!(chunk[before], OpCode::Negate as u8);
+	assert_eq!(chunk.lines.find_line(before), 3);
+}
+
+#[test]
+fn folding_preserves_ieee_semantics() {
+	let cases: &[(f64, f64, OpCode, f64)] = &[
+		(0., 0., OpCode::Divide, f64::NAN),
+		(1., 0., OpCode::Divide, f64::INFINITY),
+		(-1., 0., OpCode::Divide, f64::NEG_INFINITY),
+		(0., -1., OpCode::Multiply, -0.),
+		(-0., 0., OpCode::Add, 0.),
+		(-0., 0., OpCode::Subtract, -0.),
+		(f64::INFINITY, f64::INFINITY, OpCode::Subtract, f64::NAN),
+	];
+
+	for &(lhs, rhs, op, expected) in cases {
+		let mut chunk = Chunk::new();
+		chunk.set_fold_constants(true);
+		chunk.write_const(lhs, 1);
+		chunk.write_const(rhs, 1);
+		chunk.write_instr(op, 1);
+
+		let folded = folded_value(&chunk);
+		if expected.is_nan() {
+			assert!(folded.is_nan());
+		} else {
+			assert_eq!(folded.to_bits(), expected.to_bits());
+		}
+	}
+
+	// Negating 0 gives -0, not 0
+	let mut chunk = Chunk::new();
+	chunk.set_fold_constants(true);
+	chunk.write_const(0., 1);
+	chunk.write_instr(OpCode::Negate, 1);
+	assert_eq!(folded_value(&chunk).to_bits(), (-0f64).to_bits());
+}
+
+/// The value pushed by a chunk which has been folded down to one instruction.
+fn folded_value(chunk: &Chunk) -> Value {
+	let op = OpCode::try_from(chunk[0]).ok();
+	let len = match op {
+		Some(OpCode::Zero) | Some(OpCode::One) => 1,
+		_ => 2,
+	};
+	assert_eq!(chunk.len(), len, "{:?}", chunk);
+
+	match op {
+		Some(OpCode::Zero) => 0.,
+		Some(OpCode::One) => 1.,
+		Some(OpCode::SmallInt) => chunk[1] as i8 as Value,
+		Some(OpCode::Constant) => chunk.constants[chunk[1] as usize],
+		_ => panic!("Unexpected instruction: {:?}", chunk),
+	}
+}
+
+#[test]
+fn folding_only_releases_unshared_constants() {
+	let mut chunk = Chunk::new();
+	chunk.set_fold_constants(true);
+	chunk.write_const(2.5, 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk.write_const(2.5, 2);
+	chunk.write_const(3.5, 2);
+	chunk.write_instr(OpCode::Multiply, 2);
+
+	// `2.5` is still used by the first instruction
+	assert_eq!(&chunk.constants[..], &[2.5, 8.75]);
+	chunk.write_const(2.5, 3);
+	assert_eq!(chunk[chunk.len() - 1], 0);
+	chunk.write_const(3.5, 3);
+	assert_eq!(chunk.constants.len(), 3);
+}
+
+#[test]
+fn folding_rewinds_the_line_table() {
+	let mut chunk = Chunk::new();
+	chunk.set_fold_constants(true);
+	let count = Lines::CHECKPOINT_INTERVAL as f64 * 2.5;
+	let mut line = 1;
+	let mut acc = 0.;
+	for i in 0..count as usize {
+		chunk.write_const(i as f64 + 0.5, line);
+		line += 1;
+		chunk.write_const(0.25, line);
+		line += 1;
+		chunk.write_instr(OpCode::Add, line);
+		line += 1;
+		chunk.write_instr(OpCode::Return, line);
+		line += 1;
+		acc += i as f64 + 0.75;
+	}
+
+	// Every addition folded into one constant on the line of its operator
+	assert_eq!(chunk.len(), count as usize * 3);
+	let mut cursor = chunk.lines.cursor();
+	for (i, offset) in (0..chunk.len()).step_by(3).enumerate() {
+		assert_eq!(chunk.lines.seek(&mut cursor, offset).line, i * 4 + 3);
+		assert_eq!(chunk.lines.find_line(offset + 1), i * 4 + 3);
+		assert_eq!(chunk.lines.find_line(offset + 2), i * 4 + 4);
+	}
+	assert_eq!(chunk.constants.iter().sum::<f64>(), acc);
+}
+
+#[test]
+fn opt_level_none_leaves_chunks_alone() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1., 1);
+	chunk.write_const(2., 1);
+	chunk.write_instr(OpCode::Add, 1);
+	let before = format!("{:?}", chunk);
+
+	let chunk = chunk.optimize(OptLevel::None);
+	assert_eq!(format!("{:?}", chunk), before);
+}
+
+#[test]
+fn peephole_pass_folds_and_rebuilds_lines() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.2, 123);
+	chunk.write_instr(OpCode::Negate, 123);
+	chunk.write_instr(OpCode::Return, 123);
+	chunk.write_const(1.2, 124);
+	chunk.write_const(3.4, 124);
+	chunk.write_instr(OpCode::Add, 124);
+	chunk.write_instr(OpCode::Return, 125);
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	let expected = r#"
+0000   123 CONSTANT           [0] '-1.2'
+0002     | RETURN
+0003   124 CONSTANT           [1] '4.6'
+0005   125 RETURN
+"#;
+	assert_eq!(&format!("\n{:?}\n", chunk), expected);
+	assert_eq!(chunk.constants.len(), 2);
+}
+
+#[test]
+fn peephole_pass_removes_identity_operands() {
+	// The left operands are whatever `Return` left on the stack, so they
+	// can't be folded
+	let identities = [
+		(-0., OpCode::Add),
+		(0., OpCode::Subtract),
+		(1., OpCode::Multiply),
+		(1., OpCode::Divide),
+	];
+	for &(value, op) in &identities {
+		let mut chunk = Chunk::new();
+		chunk.write_const(2., 1);
+		chunk.write_const(3., 1);
+		chunk.write_instr(OpCode::Return, 1);
+		chunk.write_const(value, 2);
+		chunk.write_instr(op, 2);
+
+		let chunk = chunk.optimize(OptLevel::Peephole);
+		assert_eq!(chunk.len(), 5, "{:?} {}", op, value);
+	}
+
+	// `-0 + 0` is `0`, so adding `0` has to stay
+	let mut chunk = Chunk::new();
+	chunk.write_const(2., 1);
+	chunk.write_const(3., 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk.write_const(0., 2);
+	chunk.write_instr(OpCode::Add, 2);
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	assert_eq!(&chunk[5..], &[OpCode::Zero as u8, OpCode::Add as u8]);
+}
+
+#[test]
+fn peephole_pass_fuses_constant_operands() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(7.5, 1);
+	chunk.write_const(2.5, 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk.write_const(3.5, 2);
+	chunk.write_instr(OpCode::Subtract, 2);
+	chunk.write_const(4.5, 3);
+	chunk.write_instr(OpCode::Multiply, 3);
+	chunk.write_instr(OpCode::Return, 3);
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	let expected = r#"
+0000     1 CONSTANT           [0] '7.5'
+0002     | CONSTANT           [1] '2.5'
+0004     | RETURN
+0005     2 SUBTRACT_CONSTANT  [2] '3.5'
+0007     3 MULTIPLY_CONSTANT  [3] '4.5'
+0009     | RETURN
+"#;
+	assert_eq!(&format!("\n{:?}\n", chunk), expected);
+
+	// Optimizing again splits and re-fuses them
+	let again = chunk.optimize(OptLevel::Peephole);
+	assert_eq!(&format!("\n{:?}\n", again), expected);
+}
+
+#[test]
+fn superinstructions_need_a_1_byte_handle() {
+	let mut chunk = Chunk::new();
+	for i in 0..256 {
+		chunk.write_const(i as f64 + 0.5, 1);
+	}
+	chunk.write_instr(OpCode::Return, 1);
+	chunk.write_const(1000.5, 2);
+	chunk.write_instr(OpCode::Add, 2);
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	assert_eq!(chunk[chunk.len() - 4], OpCode::Constant16 as u8);
+	assert_eq!(chunk[chunk.len() - 1], OpCode::Add as u8);
+}
+
+#[test]
+fn peephole_pass_skips_malformed_chunks() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1., 1);
+	chunk.write_const(2., 1);
+	chunk.write_instr(OpCode::Add, 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk[1] = 7;
+	let before = chunk.to_vec();
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	assert_eq!(chunk.to_vec(), before);
+}
+
+#[test]
+fn peephole_pass_skips_chunks_which_underflow() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(2., 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk.write_instr(OpCode::Negate, 2);
+	chunk.write_instr(OpCode::Negate, 2);
+	let before = chunk.to_vec();
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	assert_eq!(chunk.to_vec(), before);
+}
+
+#[test]
+fn it_writes_small_integers_as_immediates() {
+	let mut chunk = Chunk::new();
+	chunk.write_number(0., 1);
+	chunk.write_number(1., 1);
+	chunk.write_number(-128., 1);
+	chunk.write_number(127., 1);
+	chunk.write_number(-32_768., 2);
+	chunk.write_number(32_767., 2);
+
+	let expected = r#"
+0000     1 ZERO
+0001     | ONE
+0002     | SMALL_INT          -128
+0004     | SMALL_INT          127
+0006     2 SMALL_INT_16       -32768
+0009     | SMALL_INT_16       32767
+"#;
+	assert_eq!(&format!("\n{:?}\n", chunk), expected);
+	assert!(chunk.constants.is_empty());
+}
+
+#[test]
+fn other_numbers_go_in_the_constant_pool() {
+	let mut chunk = Chunk::new();
+	let values = [-0., 0.5, 32_768., -32_769., f64::NAN, f64::INFINITY];
+	for &value in &values {
+		chunk.write_number(value, 1);
+	}
+
+	assert_eq!(chunk.constants.len(), values.len());
+	for (constant, value) in chunk.constants.iter().zip(&values) {
+		assert_eq!(constant.to_bits(), value.to_bits());
+	}
+}
+
+#[test]
+fn peephole_pass_turns_small_constants_into_immediates() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1., 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk.write_const(300., 2);
+	chunk.write_instr(OpCode::Return, 2);
+
+	let chunk = chunk.optimize(OptLevel::Peephole);
+	let expected = r#"
+0000     1 ONE
+0001     | RETURN
+0002     2 SMALL_INT_16       300
+0005     | RETURN
+"#;
+	assert_eq!(&format!("\n{:?}\n", chunk), expected);
+	assert!(chunk.constants.is_empty());
+}
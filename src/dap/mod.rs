@@ -0,0 +1,523 @@
+//! A [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/)
+//! server, which drives the VM's [`Debugger`] on behalf of an editor.
+//!
+//! The server handles a single session over a pair of streams (normally stdin
+//! and stdout) and supports one thread with a single stack frame.
+
+use std::{
+	cell::RefCell,
+	io::{self, BufRead, Write},
+	mem,
+	rc::Rc,
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+	chunk::Chunk,
+	vm::{
+		debugger::{Breakpoint, Command, Frontend, Pause, PauseReason},
+		Debugger, VM,
+	},
+};
+
+const THREAD_ID: i64 = 1;
+const FRAME_ID: i64 = 1;
+
+// Handles for the `variables` request
+const STACK_REF: i64 = 1;
+const LOCALS_REF: i64 = 2;
+const GLOBALS_REF: i64 = 3;
+
+/// The largest message body the adapter will read, so that a bad header
+/// can't make it allocate without bound.
+const MAX_CONTENT_LEN: usize = 1 << 24;
+
+/// Serves a debug session until the client disconnects or closes `input`.
+///
+/// `load` turns the `program` argument of the `launch` request into a chunk,
+/// or returns a message explaining why it couldn't.
+pub fn serve<R, W, L>(input: R, output: W, mut load: L) -> io::Result<()>
+where
+	R: BufRead + 'static,
+	W: Write + 'static,
+	L: FnMut(&str) -> Result<Chunk, String>,
+{
+	let session = Rc::new(RefCell::new(Session::new(input, output)));
+
+	let mut launch = None;
+	let mut configured = false;
+
+	loop {
+		let request = match session.borrow_mut().read()? {
+			Some(request) => request,
+			None => return Ok(()),
+		};
+		let mut session_ref = session.borrow_mut();
+
+		match command(&request) {
+			"initialize" => {
+				session_ref.respond(
+					&request,
+					json!({
+						"supportsConfigurationDoneRequest": true,
+					}),
+				)?;
+				session_ref.event("initialized", json!({}))?;
+			}
+			"launch" => {
+				let args = &request["arguments"];
+				let program = args["program"].as_str().unwrap_or_default();
+				session_ref.program = program.to_string();
+
+				match load(program) {
+					Ok(chunk) => {
+						session_ref.respond(&request, json!({}))?;
+						session_ref.chunk = Some(Rc::new(chunk));
+						session_ref.verify_pending_breakpoints()?;
+						launch = Some(args["stopOnEntry"].as_bool().unwrap_or(false));
+					}
+					Err(msg) => session_ref.respond_err(&request, &msg)?,
+				}
+			}
+			"configurationDone" => {
+				configured = true;
+				session_ref.respond(&request, json!({}))?;
+			}
+			"disconnect" => {
+				return session_ref.respond(&request, json!({}));
+			}
+			_ => session_ref.dispatch(&request, None, &mut vec![])?,
+		}
+		drop(session_ref);
+
+		if let (Some(stop_on_entry), true) = (launch, configured) {
+			let chunk = match session.borrow().chunk.clone() {
+				Some(chunk) => chunk,
+				None => continue,
+			};
+
+			let mut debugger =
+				Debugger::new(Adapter(session.clone())).stop_on_entry(stop_on_entry);
+			for bp in session.borrow_mut().breakpoints.drain(..) {
+				debugger.add_breakpoint(bp);
+			}
+
+			// stdout is taken by the protocol, so the program's output is
+			// relayed to the client instead
+			let vm = VM::new();
+			vm.attach_debugger(debugger);
+			let stdout = vm.set_output(ProgramOutput(session.clone()));
+			let result = vm.interpret(&chunk);
+			vm.set_output(stdout);
+			vm.detach_debugger();
+
+			let mut session = session.borrow_mut();
+			if session.disconnected {
+				return Ok(());
+			}
+			if let Err(err) = &result {
+				session.output(err.to_diagnostic().render(None, false).trim_end())?;
+			}
+			let exit_code = if result.is_ok() { 0 } else { 1 };
+			session.event("exited", json!({ "exitCode": exit_code }))?;
+			session.event("terminated", json!({}))?;
+			launch = None;
+		}
+	}
+}
+
+struct Session<R, W> {
+	input: R,
+	output: W,
+	seq: i64,
+	program: String,
+	/// The launched program, once it's been loaded
+	chunk: Option<Rc<Chunk>>,
+	/// Breakpoints requested before the program was loaded, as source paths
+	/// and lines. They can't be verified until then.
+	pending: Vec<(Option<String>, usize)>,
+	/// Verified breakpoints set before the program started running
+	breakpoints: Vec<Breakpoint>,
+	disconnected: bool,
+}
+
+impl<R, W> Session<R, W>
+where
+	R: BufRead,
+	W: Write,
+{
+	fn new(input: R, output: W) -> Self {
+		Self {
+			input,
+			output,
+			seq: 0,
+			program: String::new(),
+			chunk: None,
+			pending: vec![],
+			breakpoints: vec![],
+			disconnected: false,
+		}
+	}
+
+	/// Reads the next message, or `None` if the client closed the stream.
+	fn read(&mut self) -> io::Result<Option<Json>> {
+		let mut content_len = None;
+		let mut header = String::new();
+		loop {
+			header.clear();
+			if self.input.read_line(&mut header)? == 0 {
+				return Ok(None);
+			}
+
+			let header = header.trim();
+			if header.is_empty() {
+				break;
+			}
+			if let Some(len) = header.strip_prefix("Content-Length:") {
+				content_len = len.trim().parse::<usize>().ok();
+			}
+		}
+
+		let len = content_len.ok_or_else(|| invalid_data("missing Content-Length"))?;
+		if len > MAX_CONTENT_LEN {
+			return Err(invalid_data("Content-Length is too large"));
+		}
+		let mut body = vec![0; len];
+		self.input.read_exact(&mut body)?;
+
+		serde_json::from_slice(&body)
+			.map(Some)
+			.map_err(|err| invalid_data(&err.to_string()))
+	}
+
+	fn send(&mut self, mut message: Json) -> io::Result<()> {
+		self.seq += 1;
+		message["seq"] = json!(self.seq);
+
+		let body = message.to_string();
+		write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+		self.output.flush()
+	}
+
+	fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+		self.send(json!({
+			"type": "response",
+			"request_seq": request["seq"],
+			"command": request["command"],
+			"success": true,
+			"body": body,
+		}))
+	}
+
+	fn respond_err(&mut self, request: &Json, message: &str) -> io::Result<()> {
+		self.send(json!({
+			"type": "response",
+			"request_seq": request["seq"],
+			"command": request["command"],
+			"success": false,
+			"message": message,
+		}))
+	}
+
+	fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+		self.send(json!({
+			"type": "event",
+			"event": event,
+			"body": body,
+		}))
+	}
+
+	fn output(&mut self, text: &str) -> io::Result<()> {
+		self.event(
+			"output",
+			json!({
+				"category": "stderr",
+				"output": format!("{}\n", text),
+			}),
+		)
+	}
+
+	fn program_output(&mut self, text: &str) -> io::Result<()> {
+		self.event(
+			"output",
+			json!({
+				"category": "stdout",
+				"output": text,
+			}),
+		)
+	}
+
+	/// Whether `path` names the launched program. Clients which leave the path
+	/// out are assumed to mean it.
+	fn is_program(&self, path: Option<&str>) -> bool {
+		path.is_none_or(|path| path == self.program)
+	}
+
+	/// Checks that a breakpoint on `line` of `path` could ever be hit, or
+	/// explains why not.
+	fn check_breakpoint(&self, path: Option<&str>, line: usize) -> Result<(), &'static str> {
+		let chunk = self.chunk.as_ref().ok_or("The program hasn't been loaded yet")?;
+		if !self.is_program(path) {
+			return Err("Breakpoints can only be set in the launched program");
+		}
+
+		let lines = chunk.lines();
+		let mut cursor = lines.cursor();
+		if (0..chunk.len()).any(|offset| lines.seek(&mut cursor, offset).line == line) {
+			Ok(())
+		} else {
+			Err("There's no code on this line")
+		}
+	}
+
+	/// Verifies the breakpoints that were requested before the program was
+	/// loaded, and tells the client which of them were set.
+	fn verify_pending_breakpoints(&mut self) -> io::Result<()> {
+		for (path, line) in mem::take(&mut self.pending) {
+			let check = self.check_breakpoint(path.as_deref(), line);
+			if check.is_ok() {
+				self.breakpoints.push(Breakpoint::Line(line));
+			}
+			self.event(
+				"breakpoint",
+				json!({ "reason": "changed", "breakpoint": breakpoint_json(line, check) }),
+			)?;
+		}
+		Ok(())
+	}
+
+	/// Handles the requests that don't resume execution. Requests which need
+	/// a paused VM fail if `pause` is `None`.
+	fn dispatch(
+		&mut self,
+		request: &Json,
+		pause: Option<&Pause>,
+		breakpoints: &mut Vec<Breakpoint>,
+	) -> io::Result<()> {
+		let args = &request["arguments"];
+
+		match (command(request), pause) {
+			("setBreakpoints", _) => {
+				let path = args["source"]["path"].as_str();
+				let lines = args["breakpoints"]
+					.as_array()
+					.map(|bps| bps.iter().filter_map(|bp| bp["line"].as_u64()))
+					.into_iter()
+					.flatten()
+					.map(|line| line as usize)
+					.collect::<Vec<_>>();
+
+				// Breakpoints requested before the program is loaded are
+				// verified once it is
+				if self.chunk.is_none() {
+					self.pending = lines
+						.iter()
+						.map(|line| (path.map(str::to_string), *line))
+						.collect();
+
+					let pending = lines
+						.iter()
+						.map(|line| breakpoint_json(*line, Err("The program hasn't been loaded yet")))
+						.collect::<Vec<_>>();
+					return self.respond(request, json!({ "breakpoints": pending }));
+				}
+
+				let checked = lines
+					.iter()
+					.map(|line| (*line, self.check_breakpoint(path, *line)))
+					.collect::<Vec<_>>();
+
+				// Setting breakpoints in some other source leaves ours alone
+				if self.is_program(path) {
+					let target = if pause.is_some() {
+						breakpoints
+					} else {
+						&mut self.breakpoints
+					};
+					target.retain(|bp| !matches!(bp, Breakpoint::Line(_)));
+					target.extend(
+						checked
+							.iter()
+							.filter(|(_, check)| check.is_ok())
+							.map(|(line, _)| Breakpoint::Line(*line)),
+					);
+				}
+
+				let verified = checked
+					.into_iter()
+					.map(|(line, check)| breakpoint_json(line, check))
+					.collect::<Vec<_>>();
+				self.respond(request, json!({ "breakpoints": verified }))
+			}
+			("setExceptionBreakpoints", _) => self.respond(request, json!({})),
+			("threads", _) => self.respond(
+				request,
+				json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+			),
+			("stackTrace", Some(pause)) => {
+				let span = pause.span;
+				let mut frame = json!({
+					"id": FRAME_ID,
+					"name": "<script>",
+					"line": span.line,
+					"column": 1,
+					"source": { "path": self.program },
+					"instructionPointerReference": format!("{:#06x}", pause.offset),
+				});
+				if span.has_columns() {
+					frame["column"] = json!(span.start + 1);
+					frame["endLine"] = json!(span.line);
+					frame["endColumn"] = json!(span.end + 1);
+				}
+				self.respond(
+					request,
+					json!({ "stackFrames": [frame], "totalFrames": 1 }),
+				)
+			}
+			("scopes", Some(_)) => self.respond(
+				request,
+				json!({ "scopes": [
+					{ "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+					{ "name": "Locals", "variablesReference": LOCALS_REF, "expensive": false },
+					{ "name": "Globals", "variablesReference": GLOBALS_REF, "expensive": false },
+				] }),
+			),
+			("variables", Some(pause)) => {
+				// The VM doesn't have local or global variables yet
+				let variables = match args["variablesReference"].as_i64() {
+					Some(STACK_REF) => pause
+						.stack
+						.iter()
+						.enumerate()
+						.rev()
+						.map(|(idx, value)| {
+							json!({
+								"name": format!("[{}]", idx),
+								"value": value.to_string(),
+								"variablesReference": 0,
+							})
+						})
+						.collect(),
+					_ => vec![],
+				};
+				self.respond(request, json!({ "variables": variables }))
+			}
+			("stackTrace", None) | ("scopes", None) | ("variables", None) => {
+				self.respond_err(request, "The program isn't paused")
+			}
+			(cmd, _) => self.respond_err(request, &format!("Unsupported request `{}`", cmd)),
+		}
+	}
+}
+
+/// Relays pauses from the VM's debugger to the client.
+struct Adapter<R, W>(Rc<RefCell<Session<R, W>>>);
+
+impl<R, W> Adapter<R, W>
+where
+	R: BufRead,
+	W: Write,
+{
+	fn pause(&mut self, pause: &Pause, breakpoints: &mut Vec<Breakpoint>) -> io::Result<Command> {
+		let mut session = self.0.borrow_mut();
+
+		let reason = match pause.reason {
+			PauseReason::Entry => "entry",
+			PauseReason::Breakpoint(_) => "breakpoint",
+			PauseReason::Step => "step",
+		};
+		session.event(
+			"stopped",
+			json!({
+				"reason": reason,
+				"threadId": THREAD_ID,
+				"allThreadsStopped": true,
+			}),
+		)?;
+
+		loop {
+			let request = match session.read()? {
+				Some(request) => request,
+				None => {
+					session.disconnected = true;
+					return Ok(Command::Quit);
+				}
+			};
+
+			let command = match command(&request) {
+				"next" => Command::StepOver,
+				"stepIn" => Command::StepInto,
+				"stepOut" => Command::StepOut,
+				"continue" => Command::Continue,
+				"disconnect" => {
+					session.disconnected = true;
+					Command::Quit
+				}
+				_ => {
+					session.dispatch(&request, Some(pause), breakpoints)?;
+					continue;
+				}
+			};
+
+			let body = match command {
+				Command::Continue => json!({ "allThreadsContinued": true }),
+				_ => json!({}),
+			};
+			session.respond(&request, body)?;
+
+			return Ok(command);
+		}
+	}
+}
+
+impl<R, W> Frontend for Adapter<R, W>
+where
+	R: BufRead,
+	W: Write,
+{
+	fn pause(&mut self, pause: &Pause, breakpoints: &mut Vec<Breakpoint>) -> Command {
+		Adapter::pause(self, pause, breakpoints).unwrap_or_else(|_| {
+			self.0.borrow_mut().disconnected = true;
+			Command::Quit
+		})
+	}
+}
+
+/// Forwards whatever the program prints to the client as `output` events.
+struct ProgramOutput<R, W>(Rc<RefCell<Session<R, W>>>);
+
+impl<R, W> Write for ProgramOutput<R, W>
+where
+	R: BufRead,
+	W: Write,
+{
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0
+			.borrow_mut()
+			.program_output(&String::from_utf8_lossy(buf))?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Describes a breakpoint for the client. There's only one source, so the
+/// line doubles as the breakpoint's ID.
+fn breakpoint_json(line: usize, check: Result<(), &str>) -> Json {
+	let mut breakpoint = json!({ "id": line, "verified": check.is_ok(), "line": line });
+	if let Err(msg) = check {
+		breakpoint["message"] = json!(msg);
+	}
+	breakpoint
+}
+
+fn command(request: &Json) -> &str {
+	request["command"].as_str().unwrap_or_default()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
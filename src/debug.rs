@@ -8,6 +8,9 @@ use crate::{
 	value::Value,
 };
 
+/// Wide enough for the longest opcode name, so operands line up.
+const OPCODE_WIDTH: usize = 17;
+
 pub trait DebugInstruction {
 	fn debug_chunk<'a, I>(
 		&mut self,
@@ -18,17 +21,19 @@ pub trait DebugInstruction {
 	where
 		I: Iterator<Item = (usize, &'a u8)> + ExactSizeIterator + JoinBytes;
 
+	#[allow(clippy::too_many_arguments)]
 	fn debug_next_instr<I: JoinBytes>(
 		&mut self,
 		bytes: &mut I,
-		lines: &Lines,
+		line: usize,
+		prev_line: Option<usize>,
 		constants: &[Value],
 		offset: usize,
 		byte: u8,
 	) -> fmt::Result;
 
 	fn print_offset(&mut self, offset: usize) -> fmt::Result;
-	fn print_line_number(&mut self, lines: &Lines, offset: usize) -> fmt::Result;
+	fn print_line_number(&mut self, line: usize, prev_line: Option<usize>) -> fmt::Result;
 	fn print_opcode(&mut self, op: OpCode) -> fmt::Result;
 	fn print_opcode_and_value(
 		&mut self,
@@ -36,6 +41,8 @@ pub trait DebugInstruction {
 		handle: usize,
 		value: Value,
 	) -> fmt::Result;
+	fn print_opcode_and_immediate(&mut self, op: OpCode, value: i16) -> fmt::Result;
+	fn print_malformed(&mut self, op: OpCode) -> fmt::Result;
 }
 
 impl<T: Write> DebugInstruction for T {
@@ -48,8 +55,13 @@ impl<T: Write> DebugInstruction for T {
 	where
 		I: Iterator<Item = (usize, &'a u8)> + ExactSizeIterator + JoinBytes,
 	{
+		let mut cursor = lines.cursor();
+		let mut prev_line = None;
+
 		while let Some((offset, byte)) = bytes.next() {
-			self.debug_next_instr(bytes, lines, constants, offset, *byte)?;
+			let line = lines.seek(&mut cursor, offset).line;
+			self.debug_next_instr(bytes, line, prev_line, constants, offset, *byte)?;
+			prev_line = Some(line);
 
 			// Insert newline if this isn't the last instruction
 			if bytes.len() > 0 {
@@ -63,33 +75,48 @@ impl<T: Write> DebugInstruction for T {
 	fn debug_next_instr<I: JoinBytes>(
 		&mut self,
 		bytes: &mut I,
-		lines: &Lines,
+		line: usize,
+		prev_line: Option<usize>,
 		constants: &[Value],
 		offset: usize,
 		byte: u8,
 	) -> fmt::Result {
 		self.print_offset(offset)?;
-		self.print_line_number(lines, offset)?;
+		self.print_line_number(line, prev_line)?;
 
 		// Print the OpCode
 		match OpCode::try_from(byte) {
-			// For constants, we also print the index of the value in the pool,
-			// followed by the value itself
+			// For constants (and ops with a constant operand), we also print the
+			// index of the value in the pool, followed by the value itself
 			Ok(
-				op @ OpCode::Constant | op @ OpCode::Constant16 | op @ OpCode::Constant24,
+				op @ OpCode::Constant
+				| op @ OpCode::Constant16
+				| op @ OpCode::Constant24
+				| op @ OpCode::AddConstant
+				| op @ OpCode::SubtractConstant
+				| op @ OpCode::MultiplyConstant
+				| op @ OpCode::DivideConstant,
 			) => {
 				let handle = match op {
-					OpCode::Constant => bytes.join_bytes(1),
 					OpCode::Constant16 => bytes.join_bytes(2),
 					OpCode::Constant24 => bytes.join_bytes(3),
-					_ => unreachable!(),
-				}
-				.ok_or(fmt::Error)?;
-
-				let value = constants[handle];
+					_ => bytes.join_bytes(1),
+				};
 
-				self.print_opcode_and_value(op, handle, value)
+				match handle.and_then(|handle| Some((handle, *constants.get(handle)?))) {
+					Some((handle, value)) => self.print_opcode_and_value(op, handle, value),
+					None => self.print_malformed(op),
+				}
 			}
+			// Immediates are printed as-is
+			Ok(op @ OpCode::SmallInt) => match bytes.join_bytes(1) {
+				Some(byte) => self.print_opcode_and_immediate(op, byte as u8 as i8 as i16),
+				None => self.print_malformed(op),
+			},
+			Ok(op @ OpCode::SmallInt16) => match bytes.join_bytes(2) {
+				Some(bytes) => self.print_opcode_and_immediate(op, bytes as u16 as i16),
+				None => self.print_malformed(op),
+			},
 			Ok(op) => self.print_opcode(op),
 			Err(OpCodeError(msg)) => write!(self, "<{}>", msg),
 		}?;
@@ -101,15 +128,8 @@ impl<T: Write> DebugInstruction for T {
 		write!(self, "{:04}  ", offset)
 	}
 
-	fn print_line_number(&mut self, lines: &Lines, offset: usize) -> fmt::Result {
-		let line = lines.find_line(offset);
-		let prev_line = if offset > 0 {
-			Some(lines.find_line(offset - 1))
-		} else {
-			None
-		};
-
-		if prev_line.is_some() && prev_line.unwrap() == line {
+	fn print_line_number(&mut self, line: usize, prev_line: Option<usize>) -> fmt::Result {
+		if prev_line == Some(line) {
 			write!(self, "   | ")
 		} else {
 			write!(self, "{:>4} ", line)
@@ -126,14 +146,23 @@ impl<T: Write> DebugInstruction for T {
 		handle: usize,
 		value: Value,
 	) -> fmt::Result {
-		write!(self, "{:<16?}  [{}] '{}'", op, handle, value)
+		write!(self, "{:<w$?}  [{}] '{}'", op, handle, value, w = OPCODE_WIDTH)
+	}
+
+	fn print_opcode_and_immediate(&mut self, op: OpCode, value: i16) -> fmt::Result {
+		write!(self, "{:<w$?}  {}", op, value, w = OPCODE_WIDTH)
+	}
+
+	/// For instructions whose operands are cut off or out of range
+	fn print_malformed(&mut self, op: OpCode) -> fmt::Result {
+		write!(self, "{:<w$?}  <malformed>", op, w = OPCODE_WIDTH)
 	}
 }
 
 pub fn print_aligned(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
 	let len = text.len();
 	let width = f.width().unwrap_or(len);
-	let pad = (width - len).max(0);
+	let pad = width.saturating_sub(len);
 	let fill = f.fill();
 
 	match f.align() {
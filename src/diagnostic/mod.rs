@@ -0,0 +1,208 @@
+//! Error reports for the terminal, in the style of rustc's.
+
+use std::fmt::Write;
+
+use crate::chunk::Span;
+
+#[cfg(test)]
+mod tests;
+
+/// A named piece of source text that diagnostics can quote from.
+#[derive(Clone, Copy)]
+pub struct Source<'a> {
+	/// What to call the source in the report, e.g. its path
+	pub name: &'a str,
+	/// The full source text
+	pub text: &'a str,
+}
+
+/// An error report, rendered rustc-style: a header with an error code, the
+/// offending source line with the span underlined, and any notes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	/// The error code, e.g. `E0001`
+	pub code: &'static str,
+	/// What went wrong
+	pub message: String,
+	/// The source the error points at, if any
+	pub span: Option<Span>,
+	/// Extra context, shown after the source
+	pub notes: Vec<Note>,
+}
+
+/// Extra context for a [`Diagnostic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+	/// The text of the note
+	pub message: String,
+	/// The source the note points at, if any
+	pub span: Option<Span>,
+}
+
+const RED: &str = "\x1b[1;31m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+const TAB_WIDTH: usize = 4;
+
+impl Diagnostic {
+	/// Creates an error with no span or notes.
+	pub fn error<M: Into<String>>(code: &'static str, message: M) -> Self {
+		Self {
+			code,
+			message: message.into(),
+			span: None,
+			notes: vec![],
+		}
+	}
+
+	/// Points the diagnostic at `span`.
+	pub fn with_span(mut self, span: Span) -> Self {
+		self.span = Some(span);
+		self
+	}
+
+	/// Attaches a note, e.g. "variable declared here", optionally pointing at
+	/// its own span.
+	pub fn with_note<M: Into<String>>(mut self, message: M, span: Option<Span>) -> Self {
+		self.notes.push(Note {
+			message: message.into(),
+			span,
+		});
+		self
+	}
+
+	/// Renders the diagnostic, quoting `source` if it's available. ANSI colors
+	/// are only used if `color` is set.
+	pub fn render(&self, source: Option<&Source>, color: bool) -> String {
+		let painter = Painter(color);
+		let gutter = self.gutter_width();
+		let mut out = String::new();
+
+		writeln!(
+			out,
+			"{}{}",
+			painter.paint(RED, &format!("error[{}]", self.code)),
+			painter.paint(BOLD, &format!(": {}", self.message)),
+		)
+		.unwrap();
+		if let Some(span) = self.span {
+			render_snippet(&mut out, &painter, RED, gutter, span, source);
+		}
+
+		let mut blank_gutter = self.span.is_some();
+		for note in &self.notes {
+			match note.span {
+				Some(span) => {
+					writeln!(out, "{}: {}", painter.paint(CYAN, "note"), note.message).unwrap();
+					render_snippet(&mut out, &painter, CYAN, gutter, span, source);
+					blank_gutter = true;
+				}
+				None => {
+					let pad = " ".repeat(gutter + 1);
+					if blank_gutter {
+						writeln!(out, "{}{}", pad, painter.paint(BLUE, "|")).unwrap();
+						blank_gutter = false;
+					}
+					writeln!(
+						out,
+						"{}{} {}: {}",
+						pad,
+						painter.paint(BLUE, "="),
+						painter.paint(BOLD, "note"),
+						note.message
+					)
+					.unwrap();
+				}
+			}
+		}
+
+		out
+	}
+
+	fn gutter_width(&self) -> usize {
+		self.notes
+			.iter()
+			.filter_map(|note| note.span)
+			.chain(self.span)
+			.map(|span| span.line.to_string().len())
+			.max()
+			.unwrap_or(0)
+	}
+}
+
+fn render_snippet(
+	out: &mut String,
+	painter: &Painter,
+	color: &str,
+	gutter: usize,
+	span: Span,
+	source: Option<&Source>,
+) {
+	let pad = " ".repeat(gutter);
+	let name = source.map(|src| src.name).unwrap_or("<script>");
+	let location = if span.has_columns() {
+		format!("{}:{}:{}", name, span.line, span.start + 1)
+	} else {
+		format!("{}:{}", name, span.line)
+	};
+	writeln!(out, "{}{} {}", pad, painter.paint(BLUE, "-->"), location).unwrap();
+
+	let text = match source.and_then(|src| src.text.lines().nth(span.line.wrapping_sub(1))) {
+		Some(text) => text,
+		None => return,
+	};
+
+	let bar = painter.paint(BLUE, "|");
+	writeln!(out, "{} {}", pad, bar).unwrap();
+	writeln!(
+		out,
+		"{} {} {}",
+		painter.paint(BLUE, &format!("{:>1$}", span.line, gutter)),
+		bar,
+		text.replace('\t', &" ".repeat(TAB_WIDTH)),
+	)
+	.unwrap();
+
+	// Underline the whole (trimmed) line if there's no column information
+	let (start, end) = if span.has_columns() {
+		(span.start.min(text.len()), span.end.min(text.len()))
+	} else {
+		let trimmed = text.trim_start();
+		(text.len() - trimmed.len(), text.trim_end().len())
+	};
+	let indent = display_width(text, 0, start);
+	let width = display_width(text, start, end).max(1);
+
+	writeln!(
+		out,
+		"{} {} {}{}",
+		pad,
+		bar,
+		" ".repeat(indent),
+		painter.paint(color, &"^".repeat(width)),
+	)
+	.unwrap();
+}
+
+/// The on-screen width of the bytes `start..end` of `text`, with tabs expanded.
+fn display_width(text: &str, start: usize, end: usize) -> usize {
+	text.char_indices()
+		.filter(|(idx, _)| *idx >= start && *idx < end)
+		.map(|(_, c)| if c == '\t' { TAB_WIDTH } else { 1 })
+		.sum()
+}
+
+struct Painter(bool);
+
+impl Painter {
+	fn paint(&self, color: &str, text: &str) -> String {
+		if self.0 {
+			format!("{}{}{}", color, text, RESET)
+		} else {
+			text.to_string()
+		}
+	}
+}
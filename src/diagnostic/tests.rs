@@ -0,0 +1,72 @@
+use crate::chunk::Span;
+
+use super::*;
+
+const SOURCE: Source = Source {
+	name: "test.lox",
+	text: "var a = 1;\nprint -(a + \"foo\");\n\tprint b;\n",
+};
+
+#[test]
+fn it_underlines_the_span() {
+	let diagnostic = Diagnostic::error("E0002", "Operands must be numbers")
+		.with_span(Span::new(2, 8, 17))
+		.with_note("variable declared here", Some(Span::new(1, 4, 5)))
+		.with_note("strings can't be added to numbers", None);
+
+	let expected = r#"
+error[E0002]: Operands must be numbers
+ --> test.lox:2:9
+  |
+2 | print -(a + "foo");
+  |         ^^^^^^^^^
+note: variable declared here
+ --> test.lox:1:5
+  |
+1 | var a = 1;
+  |     ^
+  |
+  = note: strings can't be added to numbers
+"#;
+	assert_eq!(format!("\n{}", diagnostic.render(Some(&SOURCE), false)), expected);
+}
+
+#[test]
+fn it_expands_tabs_and_underlines_whole_lines_without_columns() {
+	let diagnostic =
+		Diagnostic::error("E0002", "Undefined variable 'b'").with_span(Span::from(3));
+
+	let expected = "
+error[E0002]: Undefined variable 'b'
+ --> test.lox:3
+  |
+3 |     print b;
+  |     ^^^^^^^^
+";
+	assert_eq!(format!("\n{}", diagnostic.render(Some(&SOURCE), false)), expected);
+}
+
+#[test]
+fn it_renders_without_source() {
+	let diagnostic = Diagnostic::error("E0001", "Invalid instruction")
+		.with_span(Span::new(125, 0, 3))
+		.with_note("at bytecode offset 0x0013", None);
+
+	let expected = "
+error[E0001]: Invalid instruction
+   --> <script>:125:1
+    |
+    = note: at bytecode offset 0x0013
+";
+	assert_eq!(format!("\n{}", diagnostic.render(None, false)), expected);
+}
+
+#[test]
+fn it_uses_ansi_colors_on_request() {
+	let diagnostic = Diagnostic::error("E0003", "Execution aborted by the debugger");
+
+	assert_eq!(
+		diagnostic.render(None, true),
+		"\x1b[1;31merror[E0003]\x1b[0m\x1b[1m: Execution aborted by the debugger\x1b[0m\n"
+	);
+}
@@ -0,0 +1,38 @@
+//! A bytecode virtual machine for Lox.
+//!
+//! There's no scanner or compiler yet, so scripts are assembled by hand with
+//! [`Chunk::write_const`] and [`Chunk::write_instr`], then run on a [`VM`]:
+//!
+//! ```
+//! use lox_rs::{Chunk, OpCode, VM};
+//!
+//! let mut chunk = Chunk::new();
+//! chunk.write_const(1.2, 1);
+//! chunk.write_instr(OpCode::Negate, 1);
+//! chunk.write_instr(OpCode::Return, 1);
+//!
+//! VM::new().interpret(&chunk).unwrap();
+//! ```
+
+#![warn(missing_docs)]
+
+pub mod chunk;
+mod dap;
+mod debug;
+pub mod diagnostic;
+mod stack;
+pub mod value;
+mod vector;
+pub mod vm;
+
+pub use crate::{
+	chunk::{Chunk, OpCode, OptLevel, Span},
+	dap::serve as serve_debug_adapter,
+	value::Value,
+	vm::{Error, VM},
+};
+
+/// Runs `chunk` on a new VM with the default settings.
+pub fn interpret(chunk: &Chunk) -> vm::Result {
+	VM::new().interpret(chunk)
+}
@@ -1,16 +1,53 @@
-use chunk::{Chunk, OpCode};
+use std::{
+	env,
+	io::{self, IsTerminal},
+	process,
+};
 
-#[macro_use]
-extern crate lazy_static;
+use lox_rs::{vm, Chunk, OpCode, OptLevel, VM};
 
-mod chunk;
-mod debug;
-mod stack;
-mod value;
-mod vector;
-mod vm;
+fn main() {
+	let args = env::args().skip(1).collect::<Vec<_>>();
 
-fn main() -> vm::Result {
+	if args.first().map(String::as_str) == Some("dap") {
+		// There's no compiler yet, so every program is the built-in script
+		lox_rs::serve_debug_adapter(io::stdin().lock(), io::stdout(), |_| Ok(script()))
+			.expect("Debug adapter session failed");
+		return;
+	}
+
+	let vm = VM::new();
+	let mut opt_level = OptLevel::None;
+	for arg in &args {
+		match arg.as_str() {
+			"-O" => opt_level = OptLevel::Peephole,
+			"--trace" => {
+				vm.enable_trace(io::stderr());
+			}
+			"--trace=json" => {
+				vm.set_trace_format(vm::TraceFormat::Json);
+				vm.enable_trace(io::stderr());
+			}
+			"--debug" => {
+				vm.attach_debugger(vm::Debugger::new(vm::Console::stdio()));
+			}
+			_ => {}
+		}
+	}
+
+	if let Err(err) = vm.interpret(&script().optimize(opt_level)) {
+		let color = io::stderr().is_terminal();
+		eprint!("{}", err.to_diagnostic().render(None, color));
+
+		let code = match err {
+			vm::Error::Compile { .. } => 65,
+			_ => 70,
+		};
+		process::exit(code);
+	}
+}
+
+fn script() -> Chunk {
 	let mut chunk = Chunk::new();
 	chunk.write_const(1.2, 123);
 	chunk.write_instr(OpCode::Negate, 123);
@@ -30,5 +67,5 @@ fn main() -> vm::Result {
 	chunk.write_instr(OpCode::Negate, 125);
 	chunk.write_instr(OpCode::Return, 125);
 
-	vm::get().interpret(chunk)
+	chunk
 }
@@ -1,6 +1,6 @@
 use std::{
 	alloc::{self, Layout},
-	fmt, mem, ptr,
+	fmt, mem, ptr, slice,
 };
 
 use crate::value::Value;
@@ -8,40 +8,77 @@ use crate::value::Value;
 #[cfg(test)]
 mod tests;
 
+/// A value stack which grows as needed, up to a configurable limit.
+///
+/// The buffer moves when the stack grows, so anything which refers to a slot
+/// (like a call frame's base) should hold on to its index rather than a
+/// pointer.
 pub struct Stack<T> {
 	begin: *mut T,
 	end: *mut T,
 	size: usize,
+	cap: usize,
+	limit: usize,
 }
 
 impl<T> Stack<T> {
-	pub const MAX: usize = 256;
+	/// The capacity of the first allocation.
+	pub const INITIAL_CAPACITY: usize = 256;
+	/// The default for the most values the stack can hold.
+	pub const DEFAULT_LIMIT: usize = 1 << 16;
 
 	pub fn new() -> Self {
+		Self::with_limit(Self::DEFAULT_LIMIT)
+	}
+
+	/// Creates a stack which can hold at most `limit` values. Nothing is
+	/// allocated until the first push.
+	pub fn with_limit(limit: usize) -> Self {
 		assert!(mem::size_of::<T>() != 0);
-		let layout = Self::layout();
-		let ptr = unsafe { alloc::alloc(layout) };
 
 		Stack {
-			begin: ptr as _,
-			end: ptr as _,
+			begin: ptr::null_mut(),
+			end: ptr::null_mut(),
 			size: 0,
+			cap: 0,
+			limit,
 		}
 	}
 
-	fn layout() -> Layout {
-		Layout::array::<T>(Self::MAX).unwrap()
+	pub fn limit(&self) -> usize {
+		self.limit
+	}
+
+	/// Changes the most values the stack can hold. Lowering it below the
+	/// current size doesn't drop anything, but nothing more can be pushed
+	/// until the stack shrinks back under it.
+	pub fn set_limit(&mut self, limit: usize) {
+		self.limit = limit;
 	}
 
+	/// Panics if the stack is full. See [`Stack::try_push`].
+	#[allow(dead_code)]
 	pub fn push(&mut self, elem: T) {
-		if self.size == Self::MAX {
+		if self.try_push(elem).is_err() {
 			panic!("Stack overflow");
 		}
+	}
+
+	/// Pushes `elem`, or gives it back if the stack is already at its limit.
+	pub fn try_push(&mut self, elem: T) -> Result<(), T> {
+		if self.size >= self.limit {
+			return Err(elem);
+		}
+		if self.size == self.cap {
+			self.grow();
+		}
 		unsafe {
 			ptr::write(self.end, elem);
 			self.end = self.end.add(1)
 		}
 		self.size += 1;
+
+		Ok(())
 	}
 
 	pub fn pop(&mut self) -> Option<T> {
@@ -58,8 +95,42 @@ impl<T> Stack<T> {
 		}
 	}
 
+	/// The value `distance` slots down from the top of the stack, where 0 is
+	/// the top.
+	#[allow(dead_code)]
+	pub fn peek(&self, distance: usize) -> Option<&T> {
+		if distance >= self.size {
+			return None;
+		}
+		Some(unsafe { &*self.end.sub(distance + 1) })
+	}
+
+	/// The value at `idx`, counting from the bottom of the stack. Call frames
+	/// can add their base to get at their own slots.
+	#[allow(dead_code)]
+	pub fn get(&self, idx: usize) -> Option<&T> {
+		self.as_slice().get(idx)
+	}
+
+	/// Replaces the value at `idx`, counting from the bottom of the stack.
+	/// Returns the old value, or gives `value` back if `idx` is out of range.
+	#[allow(dead_code)]
+	pub fn set(&mut self, idx: usize, value: T) -> Result<T, T> {
+		if idx >= self.size {
+			return Err(value);
+		}
+		Ok(unsafe { ptr::replace(self.begin.add(idx), value) })
+	}
+
+	/// Pops values until there are at most `len` left.
+	pub fn truncate(&mut self, len: usize) {
+		while self.size > len {
+			self.pop();
+		}
+	}
+
 	pub fn empty(&mut self) {
-		while self.pop().is_some() {}
+		self.truncate(0);
 	}
 
 	pub fn is_empty(&self) -> bool {
@@ -70,27 +141,78 @@ impl<T> Stack<T> {
 	pub fn size(&self) -> usize {
 		self.size
 	}
+
+	/// Iterates over the live elements, from the bottom of the stack to the top.
+	pub fn iter(&self) -> slice::Iter<'_, T> {
+		self.as_slice().iter()
+	}
+
+	/// The live elements, from the bottom of the stack to the top.
+	pub fn as_slice(&self) -> &[T] {
+		if self.cap == 0 {
+			return &[];
+		}
+		unsafe { slice::from_raw_parts(self.begin, self.size) }
+	}
+
+	fn grow(&mut self) {
+		let new_cap = match self.cap {
+			0 => Self::INITIAL_CAPACITY,
+			cap => cap * 2,
+		}
+		.min(self.limit);
+
+		let new_layout = Self::layout(new_cap);
+		let new_ptr = unsafe {
+			if self.cap == 0 {
+				alloc::alloc(new_layout)
+			} else {
+				alloc::realloc(self.begin as _, Self::layout(self.cap), new_layout.size())
+			}
+		};
+		if new_ptr.is_null() {
+			alloc::handle_alloc_error(new_layout);
+		}
+
+		self.begin = new_ptr as _;
+		self.end = unsafe { self.begin.add(self.size) };
+		self.cap = new_cap;
+	}
+
+	fn layout(cap: usize) -> Layout {
+		Layout::array::<T>(cap).expect("Allocation too large")
+	}
+}
+
+impl<T> Default for Stack<T> {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl<T> Stack<T>
 where T: Copy
 {
-	pub fn mutate<F>(&mut self, mut mutate: F)
-	where F: FnMut(&mut T) {
+	/// Updates the value on top of the stack in place. Returns `None` without
+	/// calling `mutate` if the stack is empty.
+	pub fn mutate<F, R>(&mut self, mutate: F) -> Option<R>
+	where F: FnOnce(&mut T) -> R {
 		if self.is_empty() {
-			return;
+			return None;
 		}
 
 		let value = unsafe { &mut *self.end.sub(1) };
-		mutate(value);
+		Some(mutate(value))
 	}
 }
 
 impl<T> Drop for Stack<T> {
 	fn drop(&mut self) {
 		self.empty();
-		unsafe {
-			alloc::dealloc(self.begin as _, Self::layout());
+		if self.cap != 0 {
+			unsafe {
+				alloc::dealloc(self.begin as _, Self::layout(self.cap));
+			}
 		}
 	}
 }
@@ -107,19 +229,12 @@ impl<T> fmt::Debug for Stack<T>
 where T: FmtStackElement
 {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let mut ptr = self.begin;
-		let end = self.end;
-
 		write!(f, "[")?;
-		while ptr != end {
-			if ptr != self.begin {
+		for (idx, value) in self.iter().enumerate() {
+			if idx > 0 {
 				write!(f, ", ")?;
 			}
-
-			let value = unsafe { &*ptr };
 			value.fmt_value(f)?;
-
-			ptr = unsafe { ptr.add(1) };
 		}
 		write!(f, "]")
 	}
@@ -143,6 +258,6 @@ impl FmtStackElement for Value {
 
 impl FmtStackElement for &str {
 	fn fmt_to_string(&self) -> String {
-		self.to_string()
+		format!("{:?}", self)
 	}
 }
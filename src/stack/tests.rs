@@ -61,3 +61,109 @@ fn it_supports_fmt_debug() {
 	let debug = format!("{:?}", stack);
 	assert_eq!(&debug, "[]");
 }
+
+#[test]
+fn it_grows_past_its_initial_capacity() {
+	let mut stack = Stack::new();
+	let len = Stack::<usize>::INITIAL_CAPACITY * 4 + 1;
+	for i in 0..len {
+		stack.push(i);
+	}
+	assert_eq!(stack.size(), len);
+	assert!(stack.iter().copied().eq(0..len));
+
+	for i in (0..len).rev() {
+		assert_eq!(stack.pop(), Some(i));
+	}
+	assert!(stack.is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Stack overflow")]
+fn it_overflows_at_its_limit() {
+	let mut stack = Stack::with_limit(300);
+	for i in 0..=300 {
+		stack.push(i);
+	}
+}
+
+#[test]
+fn it_does_not_allocate_until_the_first_push() {
+	let stack = Stack::<f64>::with_limit(0);
+	assert!(stack.is_empty());
+	assert_eq!(stack.iter().count(), 0);
+	assert_eq!(format!("{:?}", stack), "[]");
+}
+
+#[test]
+fn it_supports_peek_get_and_set() {
+	let mut stack = Stack::new();
+	assert_eq!(stack.peek(0), None);
+	assert_eq!(stack.get(0), None);
+
+	stack.push(1);
+	stack.push(2);
+	stack.push(3);
+
+	assert_eq!(stack.peek(0), Some(&3));
+	assert_eq!(stack.peek(2), Some(&1));
+	assert_eq!(stack.peek(3), None);
+
+	assert_eq!(stack.get(0), Some(&1));
+	assert_eq!(stack.get(2), Some(&3));
+	assert_eq!(stack.get(3), None);
+
+	assert_eq!(stack.set(1, 20), Ok(2));
+	assert_eq!(stack.set(3, 40), Err(40));
+	assert!(stack.iter().copied().eq([1, 20, 3]));
+}
+
+#[test]
+fn it_truncates() {
+	let mut stack = Stack::new();
+	for i in 0..10 {
+		stack.push(i);
+	}
+
+	stack.truncate(20);
+	assert_eq!(stack.size(), 10);
+
+	stack.truncate(4);
+	assert_eq!(stack.size(), 4);
+	assert_eq!(stack.peek(0), Some(&3));
+
+	stack.push(42);
+	assert!(stack.iter().copied().eq([0, 1, 2, 3, 42]));
+}
+
+#[test]
+fn it_only_mutates_a_value_that_exists() {
+	let mut stack = Stack::new();
+	assert_eq!(stack.mutate(|value: &mut i32| *value += 1), None);
+
+	stack.push(1);
+	assert_eq!(stack.mutate(|value| { *value += 1; *value }), Some(2));
+	assert_eq!(stack.pop(), Some(2));
+}
+
+#[test]
+fn try_push_gives_values_back_at_the_limit() {
+	let mut stack = Stack::with_limit(2);
+	assert_eq!(stack.try_push(1), Ok(()));
+	assert_eq!(stack.try_push(2), Ok(()));
+	assert_eq!(stack.try_push(3), Err(3));
+	assert_eq!(stack.size(), 2);
+
+	stack.set_limit(1);
+	assert_eq!(stack.try_push(3), Err(3));
+	stack.pop();
+	assert_eq!(stack.try_push(3), Err(3));
+	stack.pop();
+	assert_eq!(stack.try_push(3), Ok(()));
+
+	stack.set_limit(Stack::<i32>::INITIAL_CAPACITY + 1);
+	for i in 0..Stack::<i32>::INITIAL_CAPACITY {
+		assert_eq!(stack.try_push(i as i32), Ok(()));
+	}
+	assert_eq!(stack.try_push(0), Err(0));
+}
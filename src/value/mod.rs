@@ -1 +1,4 @@
+//! The values scripts work with.
+
+/// A Lox value. Only numbers are supported so far.
 pub type Value = f64;
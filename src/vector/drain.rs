@@ -0,0 +1,88 @@
+use std::{
+	iter::FusedIterator,
+	ops::{Bound, RangeBounds},
+	ptr, slice,
+};
+
+use super::{into_iter::RawIter, Vector};
+
+/// A draining iterator over a range of a [`Vector`], created by
+/// [`Vector::drain`].
+pub struct Drain<'a, T> {
+	vec: &'a mut Vector<T>,
+	tail_start: usize,
+	tail_len: usize,
+	iter: RawIter<T>,
+}
+
+impl<T> Vector<T> {
+	/// Removes the elements in `range`, returning them in an iterator. The
+	/// elements after the range are moved down once the iterator is dropped,
+	/// along with any elements it didn't yield.
+	///
+	/// Panics if the range is out of bounds.
+	#[allow(dead_code)]
+	pub fn drain<R>(&mut self, range: R) -> Drain<'_, T>
+	where R: RangeBounds<usize> {
+		let len = self.len;
+		let start = match range.start_bound() {
+			Bound::Included(&idx) => idx,
+			Bound::Excluded(&idx) => idx + 1,
+			Bound::Unbounded => 0,
+		};
+		let end = match range.end_bound() {
+			Bound::Included(&idx) => idx + 1,
+			Bound::Excluded(&idx) => idx,
+			Bound::Unbounded => len,
+		};
+		assert!(start <= end && end <= len, "Drain range out of bounds");
+
+		// If the Drain is leaked, so are the drained elements and the tail,
+		// rather than being left where they could be read twice
+		self.len = start;
+		let iter = unsafe { RawIter::new(slice::from_raw_parts(self.ptr().add(start), end - start)) };
+
+		Drain {
+			vec: self,
+			tail_start: end,
+			tail_len: len - end,
+			iter,
+		}
+	}
+}
+
+impl<T> Iterator for Drain<'_, T> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<T> {
+		self.iter.next()
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.iter.size_hint()
+	}
+}
+
+impl<T> DoubleEndedIterator for Drain<'_, T> {
+	fn next_back(&mut self) -> Option<T> {
+		self.iter.next_back()
+	}
+}
+
+impl<T> ExactSizeIterator for Drain<'_, T> {}
+impl<T> FusedIterator for Drain<'_, T> {}
+
+impl<T> Drop for Drain<'_, T> {
+	fn drop(&mut self) {
+		for _ in &mut self.iter {}
+
+		let start = self.vec.len;
+		if self.tail_len > 0 {
+			unsafe {
+				let ptr = self.vec.ptr();
+				ptr::copy(ptr.add(self.tail_start), ptr.add(start), self.tail_len);
+			}
+		}
+		self.vec.len = start + self.tail_len;
+	}
+}
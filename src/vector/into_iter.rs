@@ -1,11 +1,16 @@
-use std::{mem, ptr};
+use std::{
+	alloc::{self, Layout},
+	iter::FusedIterator,
+	mem,
+	ptr::{self, NonNull},
+};
 
 use super::Vector;
 
 pub struct IntoIter<T> {
-	_inner: Vector<T>,
-	start: *const T,
-	end: *const T,
+	buf: NonNull<T>,
+	cap: usize,
+	iter: RawIter<T>,
 }
 
 impl<T> IntoIterator for Vector<T> {
@@ -13,18 +18,14 @@ impl<T> IntoIterator for Vector<T> {
 	type IntoIter = IntoIter<T>;
 
 	fn into_iter(self) -> IntoIter<T> {
-		unsafe {
-			let vec = ptr::read(&self);
-			let len = self.len;
+		let iter = unsafe { RawIter::new(&self) };
+		let buf = self.ptr;
+		let cap = self.cap;
 
-			mem::forget(self);
+		// The iterator owns the elements and the allocation now
+		mem::forget(self);
 
-			IntoIter {
-				start: vec.ptr(),
-				end: vec.ptr().add(len),
-				_inner: vec,
-			}
-		}
+		IntoIter { buf, cap, iter }
 	}
 }
 
@@ -32,21 +33,103 @@ impl<T> Iterator for IntoIter<T> {
 	type Item = T;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		if self.start == self.end {
-			None
-		} else {
-			unsafe {
-				let result = ptr::read(self.start);
-				self.start = self.start.offset(1);
+		self.iter.next()
+	}
 
-				Some(result)
-			}
-		}
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.iter.size_hint()
+	}
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		self.iter.next_back()
 	}
 }
 
+impl<T> ExactSizeIterator for IntoIter<T> {}
+impl<T> FusedIterator for IntoIter<T> {}
+
 impl<T> Drop for IntoIter<T> {
 	fn drop(&mut self) {
 		for _ in &mut *self {}
+
+		if self.cap != 0 && mem::size_of::<T>() != 0 {
+			let layout = Layout::array::<T>(self.cap).unwrap();
+			unsafe { alloc::dealloc(self.buf.as_ptr() as *mut u8, layout) }
+		}
+	}
+}
+
+/// Moves values out of a range of memory, from either end. Dropping it doesn't
+/// drop the values that haven't been read.
+///
+/// For zero-sized types, the pointers can't move, so `end` is offset from
+/// `start` by one byte per element instead.
+pub(super) struct RawIter<T> {
+	start: *const T,
+	end: *const T,
+}
+
+impl<T> RawIter<T> {
+	/// # Safety
+	///
+	/// The slice must outlive the iterator, and its values must not be used
+	/// again once they've been read.
+	pub unsafe fn new(slice: &[T]) -> Self {
+		let start = slice.as_ptr();
+		let end = if mem::size_of::<T>() == 0 {
+			start.wrapping_byte_add(slice.len())
+		} else {
+			start.add(slice.len())
+		};
+
+		Self { start, end }
+	}
+
+	fn elem_size() -> usize {
+		mem::size_of::<T>().max(1)
+	}
+}
+
+impl<T> Iterator for RawIter<T> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<T> {
+		if self.start == self.end {
+			return None;
+		}
+		unsafe {
+			if mem::size_of::<T>() == 0 {
+				self.start = self.start.wrapping_byte_add(1);
+				Some(ptr::read(NonNull::dangling().as_ptr()))
+			} else {
+				let elem = self.start;
+				self.start = self.start.add(1);
+				Some(ptr::read(elem))
+			}
+		}
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let len = (self.end as usize - self.start as usize) / Self::elem_size();
+		(len, Some(len))
+	}
+}
+
+impl<T> DoubleEndedIterator for RawIter<T> {
+	fn next_back(&mut self) -> Option<T> {
+		if self.start == self.end {
+			return None;
+		}
+		unsafe {
+			if mem::size_of::<T>() == 0 {
+				self.end = self.end.wrapping_byte_sub(1);
+				Some(ptr::read(NonNull::dangling().as_ptr()))
+			} else {
+				self.end = self.end.sub(1);
+				Some(ptr::read(self.end))
+			}
+		}
 	}
 }
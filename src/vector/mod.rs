@@ -1,19 +1,22 @@
 use std::{
 	alloc::{self, Layout},
+	iter::FromIterator,
 	mem,
 	ptr::{self, NonNull},
 };
 
 mod debug;
+mod drain;
 mod into_iter;
 mod iter;
 
-pub use into_iter::IntoIter;
+#[allow(unused_imports)]
+pub use self::drain::Drain;
+pub use self::into_iter::IntoIter;
 
 #[cfg(test)]
 mod tests;
 
-#[macro_export]
 macro_rules! vector {
 	[] => {
 		$crate::vector::Vector::new()
@@ -25,7 +28,7 @@ macro_rules! vector {
 	}};
 }
 
-pub use vector;
+pub(crate) use vector;
 
 pub struct Vector<T> {
 	pub(super) ptr: NonNull<T>,
@@ -37,20 +40,42 @@ unsafe impl<T: Send> Send for Vector<T> {}
 unsafe impl<T: Sync> Sync for Vector<T> {}
 
 impl<T> Vector<T> {
+	/// The capacity of the first allocation.
+	const MIN_CAP: usize = 8;
+
 	pub fn new() -> Self {
-		assert!(mem::size_of::<T>() != 0);
+		// Zero-sized types never need an allocation, so they start out with as
+		// much capacity as they could ever use
+		let cap = if Self::is_zst() { usize::MAX } else { 0 };
 
 		Self {
 			ptr: NonNull::dangling(),
-			cap: 0,
+			cap,
 			len: 0,
 		}
 	}
 
+	pub fn with_capacity(cap: usize) -> Self {
+		let mut vec = Self::new();
+		if cap > 0 {
+			vec.realloc(cap);
+		}
+		vec
+	}
+
+	fn is_zst() -> bool {
+		mem::size_of::<T>() == 0
+	}
+
 	pub(super) fn ptr(&self) -> *mut T {
 		self.ptr.as_ptr()
 	}
 
+	#[allow(dead_code)]
+	pub fn capacity(&self) -> usize {
+		self.cap
+	}
+
 	pub fn push(&mut self, element: T) {
 		if self.len >= self.cap {
 			self.grow();
@@ -61,20 +86,141 @@ impl<T> Vector<T> {
 		self.len += 1;
 	}
 
+	pub fn pop(&mut self) -> Option<T> {
+		if self.len == 0 {
+			return None;
+		}
+		self.len -= 1;
+		let element = unsafe { ptr::read(self.ptr().add(self.len)) };
+		self.shrink_if_sparse();
+
+		Some(element)
+	}
+
+	/// Inserts `element` at `idx`, shifting everything after it to the right.
+	///
+	/// Panics if `idx > len`.
+	#[allow(dead_code)]
+	pub fn insert(&mut self, idx: usize, element: T) {
+		assert!(idx <= self.len, "Insertion index out of bounds");
+
+		if self.len >= self.cap {
+			self.grow();
+		}
+		unsafe {
+			let ptr = self.ptr().add(idx);
+			ptr::copy(ptr, ptr.add(1), self.len - idx);
+			ptr::write(ptr, element);
+		}
+		self.len += 1;
+	}
+
+	/// Removes and returns the element at `idx`, shifting everything after it
+	/// to the left.
+	///
+	/// Panics if `idx >= len`.
+	#[allow(dead_code)]
+	pub fn remove(&mut self, idx: usize) -> T {
+		assert!(idx < self.len, "Removal index out of bounds");
+
+		self.len -= 1;
+		let element = unsafe {
+			let ptr = self.ptr().add(idx);
+			let element = ptr::read(ptr);
+			ptr::copy(ptr.add(1), ptr, self.len - idx);
+
+			element
+		};
+		self.shrink_if_sparse();
+
+		element
+	}
+
+	/// Shortens the vector to `len` elements, dropping the rest. Does nothing
+	/// if the vector is already shorter than that.
+	///
+	/// Like [`Vector::clear`], this keeps the allocation as it is.
+	pub fn truncate(&mut self, len: usize) {
+		if len >= self.len {
+			return;
+		}
+		let tail = ptr::slice_from_raw_parts_mut(unsafe { self.ptr().add(len) }, self.len - len);
+		self.len = len;
+		unsafe { ptr::drop_in_place(tail) }
+	}
+
+	pub fn clear(&mut self) {
+		self.truncate(0);
+	}
+
+	/// Makes room for at least `additional` more elements.
+	pub fn reserve(&mut self, additional: usize) {
+		let required = self.len.checked_add(additional).expect("Capacity overflow");
+		if required > self.cap {
+			self.realloc(required.max(self.cap * 2).max(Self::MIN_CAP));
+		}
+	}
+
+	pub fn extend_from_slice(&mut self, other: &[T])
+	where T: Clone {
+		self.reserve(other.len());
+		for element in other {
+			self.push(element.clone());
+		}
+	}
+
+	/// Frees any capacity beyond the current length.
+	#[allow(dead_code)]
+	pub fn shrink_to_fit(&mut self) {
+		if self.cap > self.len {
+			self.realloc(self.len);
+		}
+	}
+
 	pub(super) fn grow(&mut self) {
-		let (new_cap, new_layout) = if self.cap == 0 {
-			(8, Layout::array::<T>(8).unwrap())
-		} else {
-			let new_cap = self.cap * 2;
-			let new_layout = Layout::array::<T>(new_cap).unwrap();
+		// A ZST vector is only full once its length would overflow
+		assert!(!Self::is_zst(), "Capacity overflow");
 
-			(new_cap, new_layout)
+		let new_cap = if self.cap == 0 {
+			Self::MIN_CAP
+		} else {
+			self.cap * 2
 		};
+		self.realloc(new_cap);
+	}
 
-		assert!(
-			new_layout.size() <= isize::MAX as usize,
-			"Allocation too large"
-		);
+	/// Halves the capacity once it's less than a quarter full. Waiting until
+	/// then means alternating pushes and pops can't reallocate every time.
+	fn shrink_if_sparse(&mut self) {
+		if self.cap > Self::MIN_CAP && self.len < self.cap / 4 {
+			self.realloc((self.cap / 2).max(Self::MIN_CAP));
+		}
+	}
+
+	/// Moves the elements to an allocation with room for `new_cap` of them,
+	/// which must be at least `len`. A capacity of 0 frees the allocation.
+	///
+	/// Does nothing for zero-sized types, which are never allocated.
+	fn realloc(&mut self, new_cap: usize) {
+		debug_assert!(new_cap >= self.len);
+		if Self::is_zst() {
+			return;
+		}
+
+		if new_cap == 0 {
+			if self.cap != 0 {
+				let old_layout = Layout::array::<T>(self.cap).unwrap();
+				unsafe { alloc::dealloc(self.ptr() as *mut u8, old_layout) }
+			}
+			self.ptr = NonNull::dangling();
+			self.cap = 0;
+			return;
+		}
+
+		let new_layout = match Layout::array::<T>(new_cap) {
+			Ok(layout) if layout.size() <= isize::MAX as usize => layout,
+			_ => panic!("Allocation too large"),
+		};
 
 		let new_ptr = if self.cap == 0 {
 			unsafe { alloc::alloc(new_layout) }
@@ -93,9 +239,57 @@ impl<T> Vector<T> {
 	}
 }
 
+impl<T> Default for Vector<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Clone> Clone for Vector<T> {
+	fn clone(&self) -> Self {
+		let mut vec = Self::with_capacity(self.len);
+		vec.extend_from_slice(self);
+		vec
+	}
+}
+
+impl<T: PartialEq> PartialEq for Vector<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self[..] == other[..]
+	}
+}
+
+impl<T: Eq> Eq for Vector<T> {}
+
+impl<T> FromIterator<T> for Vector<T> {
+	fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+		let mut vec = Self::new();
+		vec.extend(iter);
+		vec
+	}
+}
+
+impl<T> Extend<T> for Vector<T> {
+	fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+		let iter = iter.into_iter();
+		self.reserve(iter.size_hint().0);
+		for element in iter {
+			self.push(element);
+		}
+	}
+}
+
+impl<'a, T: Copy + 'a> Extend<&'a T> for Vector<T> {
+	fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+		self.extend(iter.into_iter().copied());
+	}
+}
+
 impl<T> Drop for Vector<T> {
 	fn drop(&mut self) {
-		if self.cap != 0 {
+		unsafe { ptr::drop_in_place(&mut self[..]) }
+
+		if self.cap != 0 && !Self::is_zst() {
 			let layout = Layout::array::<T>(self.cap).unwrap();
 			unsafe { alloc::dealloc(self.ptr() as *mut u8, layout) }
 		}
@@ -1,3 +1,8 @@
+use std::{
+	cell::Cell,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
 use crate::chunk::OpCode;
 
 use super::*;
@@ -58,3 +63,258 @@ fn by_ref_mutable_iterator() {
 	assert_eq!(codes[2], OpCode::Return);
 	assert_eq!(codes[3], OpCode::Constant);
 }
+
+#[test]
+fn truncate_drops_the_tail() {
+	use std::rc::Rc;
+
+	let item = Rc::new(());
+	let mut vec = vector![item.clone(), item.clone(), item.clone()];
+	vec.truncate(5);
+	assert_eq!(vec.len(), 3);
+
+	vec.truncate(1);
+	assert_eq!(vec.len(), 1);
+	assert_eq!(Rc::strong_count(&item), 2);
+
+	vec.push(item.clone());
+	assert_eq!(vec.len(), 2);
+}
+
+#[test]
+fn push_and_pop() {
+	let mut vec = Vector::new();
+	assert_eq!(vec.pop(), None::<i32>);
+
+	for i in 0..100 {
+		vec.push(i);
+	}
+	for i in (0..100).rev() {
+		assert_eq!(vec.pop(), Some(i));
+	}
+	assert!(vec.is_empty());
+}
+
+#[test]
+fn insert_and_remove() {
+	let mut vec = vector![1, 2, 4];
+	vec.insert(2, 3);
+	vec.insert(0, 0);
+	vec.insert(5, 5);
+	assert_eq!(&vec[..], &[0, 1, 2, 3, 4, 5]);
+
+	assert_eq!(vec.remove(0), 0);
+	assert_eq!(vec.remove(2), 3);
+	assert_eq!(vec.remove(3), 5);
+	assert_eq!(&vec[..], &[1, 2, 4]);
+}
+
+#[test]
+#[should_panic(expected = "Insertion index out of bounds")]
+fn insert_past_the_end_panics() {
+	let mut vec = vector![1, 2];
+	vec.insert(3, 3);
+}
+
+#[test]
+#[should_panic(expected = "Removal index out of bounds")]
+fn remove_past_the_end_panics() {
+	let mut vec = vector![1, 2];
+	vec.remove(2);
+}
+
+#[test]
+fn capacity_management() {
+	let mut vec = Vector::<u8>::with_capacity(3);
+	assert_eq!(vec.capacity(), 3);
+	assert!(vec.is_empty());
+
+	vec.reserve(2);
+	assert_eq!(vec.capacity(), 3);
+	vec.reserve(10);
+	assert!(vec.capacity() >= 10);
+
+	vec.extend_from_slice(&[1, 2, 3]);
+	vec.shrink_to_fit();
+	assert_eq!(vec.capacity(), 3);
+	assert_eq!(&vec[..], &[1, 2, 3]);
+
+	vec.clear();
+	assert_eq!(vec.capacity(), 3);
+	vec.shrink_to_fit();
+	assert_eq!(vec.capacity(), 0);
+
+	vec.push(4);
+	assert_eq!(&vec[..], &[4]);
+}
+
+#[test]
+fn it_shrinks_once_mostly_empty() {
+	let mut vec = (0..64).collect::<Vector<u32>>();
+	let cap = vec.capacity();
+
+	// Still a quarter full
+	while vec.len() > cap / 4 {
+		vec.pop();
+	}
+	assert_eq!(vec.capacity(), cap);
+
+	vec.pop();
+	assert_eq!(vec.capacity(), cap / 2);
+	assert_eq!(&vec[..], &(0..15).collect::<Vec<_>>()[..]);
+
+	// Popping everything settles at the minimum capacity
+	while vec.pop().is_some() {}
+	assert_eq!(vec.capacity(), 8);
+}
+
+#[test]
+fn clone_eq_and_collect() {
+	let vec = (1..=5).map(|n| n.to_string()).collect::<Vector<_>>();
+	let copy = vec.clone();
+	assert_eq!(vec, copy);
+	assert_ne!(vec, vector!["1".to_string()]);
+
+	let mut nums = vector![1, 2];
+	nums.extend(vec![3, 4]);
+	nums.extend(&[5, 6]);
+	assert_eq!(nums, (1..=6).collect());
+}
+
+/// Counts how many times its values have been dropped.
+#[derive(Clone)]
+struct DropCounter<'a>(&'a Cell<usize>);
+
+impl Drop for DropCounter<'_> {
+	fn drop(&mut self) {
+		self.0.set(self.0.get() + 1);
+	}
+}
+
+#[test]
+fn it_drops_its_elements() {
+	let drops = Cell::new(0);
+	let vec = (0..10).map(|_| DropCounter(&drops)).collect::<Vector<_>>();
+	drop(vec);
+	assert_eq!(drops.get(), 10);
+
+	// Miri would catch these leaking
+	let strings = vector!["a".to_string(), "b".to_string()];
+	drop(strings);
+}
+
+#[test]
+fn into_iter_is_double_ended() {
+	let mut iter = vector!["a", "b", "c", "d"].into_iter();
+	assert_eq!(iter.len(), 4);
+	assert_eq!(iter.next(), Some("a"));
+	assert_eq!(iter.next_back(), Some("d"));
+	assert_eq!(iter.size_hint(), (2, Some(2)));
+	assert_eq!(iter.collect::<Vec<_>>(), vec!["b", "c"]);
+
+	let reversed = vector![1, 2, 3].into_iter().rev().collect::<Vector<_>>();
+	assert_eq!(reversed, vector![3, 2, 1]);
+}
+
+#[test]
+fn into_iter_drops_what_it_didnt_yield() {
+	let drops = Cell::new(0);
+	let vec = (0..5).map(|_| DropCounter(&drops)).collect::<Vector<_>>();
+
+	let mut iter = vec.into_iter();
+	drop(iter.next());
+	drop(iter.next_back());
+	assert_eq!(drops.get(), 2);
+
+	drop(iter);
+	assert_eq!(drops.get(), 5);
+}
+
+#[test]
+fn drain_removes_a_range() {
+	let mut vec = (0..10).collect::<Vector<_>>();
+	let drained = vec.drain(2..5).collect::<Vec<_>>();
+	assert_eq!(drained, vec![2, 3, 4]);
+	assert_eq!(vec, vector![0, 1, 5, 6, 7, 8, 9]);
+
+	let mut drain = vec.drain(..=1);
+	assert_eq!(drain.len(), 2);
+	assert_eq!(drain.next_back(), Some(1));
+	drop(drain);
+	assert_eq!(vec, vector![5, 6, 7, 8, 9]);
+
+	assert_eq!(vec.drain(3..).count(), 2);
+	assert_eq!(vec.drain(..).rev().collect::<Vec<_>>(), vec![7, 6, 5]);
+	assert!(vec.is_empty());
+}
+
+#[test]
+fn drain_drops_what_it_didnt_yield() {
+	let drops = Cell::new(0);
+	let mut vec = (0..6).map(|_| DropCounter(&drops)).collect::<Vector<_>>();
+
+	let mut drain = vec.drain(1..4);
+	drop(drain.next());
+	assert_eq!(drops.get(), 1);
+	drop(drain);
+	assert_eq!(drops.get(), 3);
+	assert_eq!(vec.len(), 3);
+
+	drop(vec);
+	assert_eq!(drops.get(), 6);
+}
+
+#[test]
+fn leaking_a_drain_leaks_the_tail() {
+	let mut vec = vector![1, 2, 3, 4];
+	mem::forget(vec.drain(1..2));
+	assert_eq!(vec, vector![1]);
+}
+
+#[test]
+#[should_panic(expected = "Drain range out of bounds")]
+fn drain_past_the_end_panics() {
+	let mut vec = vector![1, 2];
+	vec.drain(1..3);
+}
+
+#[test]
+fn it_supports_zero_sized_types() {
+	let mut vec = Vector::new();
+	for _ in 0..100 {
+		vec.push(());
+	}
+	assert_eq!(vec.len(), 100);
+	assert_eq!(vec.capacity(), usize::MAX);
+
+	vec.insert(50, ());
+	vec.remove(0);
+	assert_eq!(vec.pop(), Some(()));
+	vec.shrink_to_fit();
+	assert_eq!(vec.len(), 99);
+
+	assert_eq!(vec.drain(..9).count(), 9);
+	assert_eq!(vec.len(), 90);
+
+	let mut iter = vec.clone().into_iter();
+	assert_eq!(iter.len(), 90);
+	assert_eq!(iter.next_back(), Some(()));
+	assert_eq!(iter.count(), 89);
+}
+
+#[test]
+fn it_drops_zero_sized_elements() {
+	struct Zst;
+	static DROPS: AtomicUsize = AtomicUsize::new(0);
+	impl Drop for Zst {
+		fn drop(&mut self) {
+			DROPS.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	let vec = vector![Zst, Zst, Zst];
+	let mut iter = vec.into_iter();
+	drop(iter.next());
+	drop(iter);
+	assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+}
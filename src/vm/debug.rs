@@ -1,30 +1,54 @@
-#[cfg(debug_assertions)]
 use std::{
-	cell::UnsafeCell,
-	fmt::{Alignment, Write},
+	cell::{Cell, UnsafeCell},
+	fmt::{Alignment, Write as _},
+	io,
 };
 
-#[cfg(debug_assertions)]
 use Alignment::*;
 
 use crate::{
-	chunk::{Lines, OpCode},
+	chunk::{OpCode, Span},
+	debug::Repeat,
 	stack::{FmtStackElement, Stack},
 	value::Value,
 };
 
-#[cfg(debug_assertions)]
-use crate::debug::Repeat;
+/// The shape of the execution trace written by the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+	/// Column-aligned, human-readable text.
+	Text,
+	/// JSON Lines: one object per executed instruction.
+	Json,
+}
 
-#[cfg(debug_assertions)]
+/// Writes a trace of each executed instruction to a sink.
+///
+/// Tracing is disabled until a sink is attached with [`Disassembler::attach`],
+/// and every `write_*` method is a no-op while it's disabled.
 pub(super) struct Disassembler {
+	sink: UnsafeCell<Option<Box<dyn io::Write>>>,
+	format: Cell<TraceFormat>,
 	buf: UnsafeCell<String>,
 	col: UnsafeCell<usize>,
+	prev_line: Cell<Option<usize>>,
+	record: UnsafeCell<Record>,
+}
+
+/// The fields of a single JSON trace entry, collected over the course of an
+/// instruction and serialized on flush.
+#[derive(Default)]
+struct Record {
+	offset: usize,
+	line: usize,
+	depth: usize,
+	op: Option<OpCode>,
+	operands: Vec<i64>,
+	constant: Option<Value>,
 }
 
 // TODO - Replace messy crate::debug module with this
 
-#[cfg(debug_assertions)]
 impl Disassembler {
 	// Column offsets for the output
 	const ADDR: usize = 0;
@@ -34,14 +58,54 @@ impl Disassembler {
 
 	pub fn new() -> Self {
 		Self {
+			sink: UnsafeCell::new(None),
+			format: Cell::new(TraceFormat::Text),
 			buf: UnsafeCell::new(String::new()),
 			col: UnsafeCell::new(Self::ADDR),
+			prev_line: Cell::new(None),
+			record: UnsafeCell::new(Record::default()),
 		}
 	}
 
-	pub fn write_preamble(&self, offset: usize, lines: &Lines) {
+	pub fn set_format(&self, format: TraceFormat) {
+		self.buf().clear();
+		self.format.set(format);
+	}
+
+	pub fn attach(&self, sink: Box<dyn io::Write>) -> Option<Box<dyn io::Write>> {
+		self.buf().clear();
+		self.sink().replace(sink)
+	}
+
+	pub fn detach(&self) -> Option<Box<dyn io::Write>> {
+		self.buf().clear();
+		self.sink().take()
+	}
+
+	#[inline]
+	pub fn is_enabled(&self) -> bool {
+		self.sink().is_some()
+	}
+
+	pub fn write_preamble(&self, offset: usize, span: Span, depth: usize) {
+		if !self.is_enabled() {
+			return;
+		}
+		// Discard anything left over from an instruction that bailed early
+		self.buf().clear();
+
+		if self.format.get() == TraceFormat::Json {
+			*self.record() = Record {
+				offset,
+				line: span.line,
+				depth,
+				..Record::default()
+			};
+			return;
+		}
+
 		self.write_offset(offset);
-		self.write_line(offset, lines);
+		self.write_line(offset, span.line);
 	}
 
 	fn write_offset(&self, offset: usize) {
@@ -51,15 +115,11 @@ impl Disassembler {
 		self.write(data, Left);
 	}
 
-	fn write_line(&self, offset: usize, lines: &Lines) {
+	fn write_line(&self, offset: usize, line: usize) {
 		self.set_col(Self::LINE);
 
-		let line = lines.find_line(offset);
-		let prev_line = if offset > 0 {
-			Some(lines.find_line(offset - 1))
-		} else {
-			None
-		};
+		let prev_line = if offset > 0 { self.prev_line.get() } else { None };
+		self.prev_line.set(Some(line));
 
 		let data = match prev_line {
 			Some(prev) if prev == line => "|".to_string(),
@@ -70,18 +130,63 @@ impl Disassembler {
 	}
 
 	pub fn write_opcode(&self, op: OpCode) {
+		if !self.is_enabled() {
+			return;
+		}
+		if self.format.get() == TraceFormat::Json {
+			self.record().op = Some(op);
+			return;
+		}
 		self.set_col(Self::INSTR);
 
 		let data = format!("{:#04x} {:?}", op as u8, op);
 		self.write(data, Left);
 	}
 
+	/// Records a constant-pool read. Text traces only show the value, while
+	/// JSON traces include both the handle and the decoded constant.
+	pub fn write_constant(&self, handle: usize, value: Value) {
+		if !self.is_enabled() {
+			return;
+		}
+		if self.format.get() == TraceFormat::Json {
+			let record = self.record();
+			record.operands.push(handle as i64);
+			record.constant = Some(value);
+			return;
+		}
+		self.write_value(value);
+	}
+
+	/// Records an integer operand which is pushed as it is. JSON traces list
+	/// it with the operands.
+	pub fn write_immediate(&self, value: i16) {
+		if !self.is_enabled() {
+			return;
+		}
+		if self.format.get() == TraceFormat::Json {
+			self.record().operands.push(value as i64);
+			return;
+		}
+		self.write_value(value as Value);
+	}
+
 	pub fn write_value(&self, value: Value) {
+		if !self.is_enabled() || self.format.get() == TraceFormat::Json {
+			return;
+		}
 		let data = format!(" <{}>", value.fmt_to_string());
 		self.write(data, Left);
 	}
 
 	pub fn write_stack(&self, stack: &Stack<Value>) {
+		if !self.is_enabled() {
+			return;
+		}
+		if self.format.get() == TraceFormat::Json {
+			self.write_record(stack);
+			return;
+		}
 		self.set_col(Self::STACK);
 
 		let data = format!("{:?}", stack);
@@ -89,11 +194,53 @@ impl Disassembler {
 	}
 
 	pub fn flush(&self) {
+		let sink = match self.sink() {
+			Some(sink) => sink,
+			None => return,
+		};
 		let buf = self.buf();
-		println!("{}", buf);
+
+		// A broken trace sink shouldn't take the script down with it
+		let _ = writeln!(sink, "{}", buf);
 		buf.clear();
 	}
 
+	fn write_record(&self, stack: &Stack<Value>) {
+		let record = self.record();
+		let buf = self.buf();
+
+		write!(buf, r#"{{"offset":{},"line":{},"op":"#, record.offset, record.line)
+			.unwrap();
+		match record.op {
+			Some(op) => write!(buf, r#""{:?}""#, op).unwrap(),
+			None => buf.push_str("null"),
+		}
+
+		buf.push_str(r#","operands":["#);
+		for (idx, operand) in record.operands.iter().enumerate() {
+			if idx > 0 {
+				buf.push(',');
+			}
+			write!(buf, "{}", operand).unwrap();
+		}
+
+		buf.push_str(r#"],"constant":"#);
+		match record.constant {
+			Some(value) => write_json_number(buf, value),
+			None => buf.push_str("null"),
+		}
+
+		buf.push_str(r#","stack":["#);
+		for (idx, value) in stack.iter().enumerate() {
+			if idx > 0 {
+				buf.push(',');
+			}
+			write_json_number(buf, *value);
+		}
+
+		write!(buf, r#"],"depth":{}}}"#, record.depth).unwrap();
+	}
+
 	fn write(&self, content: String, align: Alignment) {
 		let buf = self.buf();
 		match align {
@@ -112,6 +259,18 @@ impl Disassembler {
 		}
 	}
 
+	#[inline]
+	#[allow(clippy::mut_from_ref)]
+	fn sink(&self) -> &mut Option<Box<dyn io::Write>> {
+		unsafe { &mut *self.sink.get() }
+	}
+
+	#[inline]
+	#[allow(clippy::mut_from_ref)]
+	fn record(&self) -> &mut Record {
+		unsafe { &mut *self.record.get() }
+	}
+
 	#[inline]
 	#[allow(clippy::mut_from_ref)]
 	fn buf(&self) -> &mut String {
@@ -130,16 +289,12 @@ impl Disassembler {
 	}
 }
 
-#[cfg(not(debug_assertions))]
-pub(super) struct Disassembler;
-
-#[cfg(not(debug_assertions))]
-#[rustfmt::skip]
-impl Disassembler {
-	#[inline(always)] pub fn new() -> Self { Self }
-	#[inline(always)] pub fn write_preamble(&self, _: usize, _: &Lines) {}
-	#[inline(always)] pub fn write_opcode(&self, _: OpCode) {}
-	#[inline(always)] pub fn write_value(&self, _: Value) {}
-	#[inline(always)] pub fn write_stack(&self, _: &Stack<Value>) {}
-	#[inline(always)] pub fn flush(&self) {}
+/// JSON has no representation for NaN or the infinities, so those are written
+/// as strings instead.
+fn write_json_number(buf: &mut String, value: Value) {
+	if value.is_finite() {
+		write!(buf, "{}", value).unwrap();
+	} else {
+		write!(buf, r#""{}""#, value).unwrap();
+	}
 }
@@ -0,0 +1,181 @@
+use std::{
+	convert::TryFrom,
+	io::{self, BufRead, Write},
+};
+
+use crate::chunk::{OpCode, OpCodeError};
+
+use super::{Breakpoint, Command, Frontend, Pause, PauseReason};
+
+const HELP: &str = "\
+Commands:
+  s, step              Step to the next instruction
+  n, next              Step over calls in the current frame
+  o, out               Step out of the current frame
+  c, continue          Run until the next breakpoint
+  b, break <line>      Break at the first instruction of a source line
+  b, break @<offset>   Break at a bytecode offset (decimal or 0x-prefixed hex)
+  d, delete <...>      Remove a breakpoint, using the same syntax as `break`
+  bl, breakpoints      List breakpoints
+  p, stack             Print the value stack
+  locals               Print the current frame's locals
+  globals              Print global variables
+  q, quit              Stop the script
+  h, help              Print this message";
+
+/// A line-oriented, gdb-style debugger frontend.
+///
+/// Reads commands from `input` and writes its output to `output`, so it can be
+/// driven by a terminal or scripted in tests.
+pub struct Console<R, W> {
+	input: R,
+	output: W,
+}
+
+impl Console<io::StdinLock<'static>, io::Stderr> {
+	/// A console which reads commands from stdin and writes to stderr.
+	pub fn stdio() -> Self {
+		Self::new(io::stdin().lock(), io::stderr())
+	}
+}
+
+impl<R, W> Console<R, W>
+where
+	R: BufRead,
+	W: Write,
+{
+	/// A console which reads commands from `input` and writes to `output`.
+	pub fn new(input: R, output: W) -> Self {
+		Self { input, output }
+	}
+
+	fn report(&mut self, pause: &Pause) -> io::Result<()> {
+		match pause.reason {
+			PauseReason::Entry => write!(self.output, "Paused on entry")?,
+			PauseReason::Breakpoint(bp) => write!(self.output, "Hit breakpoint at {}", bp)?,
+			PauseReason::Step => write!(self.output, "Stepped")?,
+		}
+
+		let instr = match OpCode::try_from(pause.byte) {
+			Ok(op) => format!("{:#04x} {:?}", op as u8, op),
+			Err(OpCodeError(msg)) => format!("<{}>", msg),
+		};
+		writeln!(
+			self.output,
+			": {:#06x} (line {})  {}",
+			pause.offset, pause.span, instr
+		)
+	}
+
+	fn run_command(
+		&mut self,
+		line: &str,
+		pause: &Pause,
+		breakpoints: &mut Vec<Breakpoint>,
+	) -> io::Result<Option<Command>> {
+		let mut words = line.split_whitespace();
+		let cmd = match words.next() {
+			Some(cmd) => cmd,
+			None => return Ok(None),
+		};
+		let arg = words.next();
+
+		match cmd {
+			"s" | "step" => return Ok(Some(Command::StepInto)),
+			"n" | "next" => return Ok(Some(Command::StepOver)),
+			"o" | "out" => return Ok(Some(Command::StepOut)),
+			"c" | "continue" => return Ok(Some(Command::Continue)),
+			"q" | "quit" => return Ok(Some(Command::Quit)),
+			"b" | "break" => match arg.and_then(parse_breakpoint) {
+				Some(bp) => {
+					if !breakpoints.contains(&bp) {
+						breakpoints.push(bp);
+					}
+					writeln!(self.output, "Breakpoint set at {}", bp)?;
+				}
+				None => writeln!(self.output, "Usage: break <line> | break @<offset>")?,
+			},
+			"d" | "delete" => match arg.and_then(parse_breakpoint) {
+				Some(bp) => {
+					if let Some(idx) = breakpoints.iter().position(|it| *it == bp) {
+						breakpoints.remove(idx);
+						writeln!(self.output, "Deleted breakpoint at {}", bp)?;
+					} else {
+						writeln!(self.output, "No breakpoint at {}", bp)?;
+					}
+				}
+				None => writeln!(self.output, "Usage: delete <line> | delete @<offset>")?,
+			},
+			"bl" | "breakpoints" => {
+				if breakpoints.is_empty() {
+					writeln!(self.output, "No breakpoints")?;
+				}
+				for (idx, bp) in breakpoints.iter().enumerate() {
+					writeln!(self.output, "{:>3}: {}", idx, bp)?;
+				}
+			}
+			"p" | "stack" => {
+				if pause.stack.is_empty() {
+					writeln!(self.output, "<empty>")?;
+				}
+				for (idx, value) in pause.stack.iter().enumerate().rev() {
+					writeln!(self.output, "{:>3}: {}", idx, value)?;
+				}
+			}
+			// The VM doesn't have local or global variables yet
+			"locals" => writeln!(self.output, "No locals in frame #{}", pause.depth)?,
+			"globals" => writeln!(self.output, "No globals")?,
+			"h" | "help" => writeln!(self.output, "{}", HELP)?,
+			_ => writeln!(self.output, "Unknown command `{}`, try `help`", cmd)?,
+		}
+
+		Ok(None)
+	}
+}
+
+impl<R, W> Frontend for Console<R, W>
+where
+	R: BufRead,
+	W: Write,
+{
+	fn pause(&mut self, pause: &Pause, breakpoints: &mut Vec<Breakpoint>) -> Command {
+		if self.report(pause).is_err() {
+			return Command::Quit;
+		}
+
+		let mut line = String::new();
+		loop {
+			if write!(self.output, "(lox) ")
+				.and_then(|_| self.output.flush())
+				.is_err()
+			{
+				return Command::Quit;
+			}
+
+			line.clear();
+			match self.input.read_line(&mut line) {
+				// Treat EOF like gdb does
+				Ok(0) | Err(_) => return Command::Quit,
+				Ok(_) => {}
+			}
+
+			match self.run_command(line.trim(), pause, breakpoints) {
+				Ok(Some(command)) => return command,
+				Ok(None) => {}
+				Err(_) => return Command::Quit,
+			}
+		}
+	}
+}
+
+fn parse_breakpoint(arg: &str) -> Option<Breakpoint> {
+	if let Some(offset) = arg.strip_prefix('@') {
+		let offset = match offset.strip_prefix("0x") {
+			Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+			None => offset.parse().ok()?,
+		};
+		Some(Breakpoint::Offset(offset))
+	} else {
+		arg.parse().ok().map(Breakpoint::Line)
+	}
+}
@@ -0,0 +1,192 @@
+//! Pausing, stepping and breakpoints, driven by a pluggable [`Frontend`].
+
+use std::fmt;
+
+use crate::{
+	chunk::Span,
+	value::Value,
+};
+
+pub use self::console::Console;
+
+mod console;
+
+/// A location in the bytecode where execution should pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+	/// Pauses at the first instruction generated for a source line.
+	Line(usize),
+	/// Pauses at the instruction starting at a bytecode offset.
+	Offset(usize),
+}
+
+impl fmt::Display for Breakpoint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Line(line) => write!(f, "line {}", line),
+			Self::Offset(offset) => write!(f, "offset {:#06x}", offset),
+		}
+	}
+}
+
+/// What the VM should do after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+	/// Pause again before the very next instruction.
+	StepInto,
+	/// Pause before the next instruction in the current frame or its caller.
+	StepOver,
+	/// Pause before the next instruction in the caller of the current frame.
+	StepOut,
+	/// Run until the next breakpoint.
+	Continue,
+	/// Stop executing the script.
+	Quit,
+}
+
+/// Why the VM paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+	/// The script is about to start.
+	Entry,
+	/// A breakpoint was hit.
+	Breakpoint(Breakpoint),
+	/// A step finished.
+	Step,
+}
+
+/// The state of the VM while it's paused, just before executing the
+/// instruction at `offset`, which was generated from the source at `span`.
+pub struct Pause<'a> {
+	/// Why the VM paused
+	pub reason: PauseReason,
+	/// The offset of the next instruction
+	pub offset: usize,
+	/// The source the next instruction was generated from
+	pub span: Span,
+	/// The next instruction's opcode byte
+	pub byte: u8,
+	/// How many call frames deep execution is
+	pub depth: usize,
+	/// The value stack, from the bottom up
+	pub stack: &'a [Value],
+}
+
+/// Drives the debugger whenever the VM pauses: reports the VM's state to the
+/// user and decides how execution should proceed.
+pub trait Frontend {
+	/// Called with the VM paused. The frontend can add and remove
+	/// `breakpoints` before returning what to do next.
+	fn pause(&mut self, pause: &Pause, breakpoints: &mut Vec<Breakpoint>) -> Command;
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+	Run,
+	StepInto,
+	StepOver { depth: usize },
+	StepOut { depth: usize },
+}
+
+/// Decides where the VM should pause, and hands control to a [`Frontend`]
+/// when it does.
+pub struct Debugger {
+	frontend: Box<dyn Frontend>,
+	breakpoints: Vec<Breakpoint>,
+	mode: Mode,
+	stop_on_entry: bool,
+	prev_line: Option<usize>,
+}
+
+impl Debugger {
+	/// Creates a debugger which pauses before the first instruction.
+	pub fn new<F>(frontend: F) -> Self
+	where F: Frontend + 'static {
+		Self {
+			frontend: Box::new(frontend),
+			breakpoints: vec![],
+			mode: Mode::Run,
+			stop_on_entry: true,
+			prev_line: None,
+		}
+	}
+
+	/// Sets whether to pause before the first instruction. Defaults to `true`.
+	pub fn stop_on_entry(mut self, stop: bool) -> Self {
+		self.stop_on_entry = stop;
+		self
+	}
+
+	/// Adds a breakpoint, unless it's already set.
+	pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+		if !self.breakpoints.contains(&breakpoint) {
+			self.breakpoints.push(breakpoint);
+		}
+	}
+
+	/// The breakpoints which are set.
+	pub fn breakpoints(&self) -> &[Breakpoint] {
+		&self.breakpoints
+	}
+
+	/// Called by the VM before executing each instruction. Blocks on the
+	/// frontend if execution should pause here, and returns `false` if the
+	/// script should be stopped.
+	pub(super) fn before_instr(
+		&mut self,
+		offset: usize,
+		byte: u8,
+		span: Span,
+		depth: usize,
+		stack: &[Value],
+	) -> bool {
+		if offset == 0 {
+			self.prev_line = None;
+		}
+		let line = span.line;
+		let entered_line = self.prev_line != Some(line);
+		self.prev_line = Some(line);
+
+		let reason = if offset == 0 && self.stop_on_entry {
+			Some(PauseReason::Entry)
+		} else if let Some(bp) = self.breakpoints.iter().find(|bp| match bp {
+			Breakpoint::Line(l) => *l == line && entered_line,
+			Breakpoint::Offset(o) => *o == offset,
+		}) {
+			Some(PauseReason::Breakpoint(*bp))
+		} else {
+			match self.mode {
+				Mode::Run => None,
+				Mode::StepInto => Some(PauseReason::Step),
+				Mode::StepOver { depth: d } if depth <= d => Some(PauseReason::Step),
+				Mode::StepOut { depth: d } if depth < d => Some(PauseReason::Step),
+				_ => None,
+			}
+		};
+
+		let reason = match reason {
+			Some(reason) => reason,
+			None => return true,
+		};
+
+		let pause = Pause {
+			reason,
+			offset,
+			span,
+			byte,
+			depth,
+			stack,
+		};
+		let command = self.frontend.pause(&pause, &mut self.breakpoints);
+
+		self.mode = match command {
+			Command::StepInto => Mode::StepInto,
+			Command::StepOver => Mode::StepOver { depth },
+			Command::StepOut => Mode::StepOut { depth },
+			Command::Continue => Mode::Run,
+			Command::Quit => return false,
+		};
+
+		true
+	}
+}
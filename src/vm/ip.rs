@@ -0,0 +1,76 @@
+use std::marker::PhantomData;
+
+/// An instruction pointer into a chunk's bytecode.
+///
+/// Reads are bounds-checked against the end of the code, so malformed bytecode
+/// is reported instead of running off the end of the chunk.
+pub(super) struct Ip<'a> {
+	start: *const u8,
+	ptr: *const u8,
+	end: *const u8,
+	code: PhantomData<&'a [u8]>,
+}
+
+impl<'a> Ip<'a> {
+	pub fn new(code: &'a [u8]) -> Self {
+		let range = code.as_ptr_range();
+		Self {
+			start: range.start,
+			ptr: range.start,
+			end: range.end,
+			code: PhantomData,
+		}
+	}
+
+	/// The offset of the next byte to be read.
+	pub fn offset(&self) -> usize {
+		self.ptr as usize - self.start as usize
+	}
+
+	fn remaining(&self) -> usize {
+		self.end as usize - self.ptr as usize
+	}
+
+	/// Reads the next byte, along with its offset.
+	#[inline(always)]
+	pub fn next(&mut self) -> Option<(usize, u8)> {
+		if self.ptr == self.end {
+			return None;
+		}
+		let offset = self.offset();
+		unsafe {
+			let byte = *self.ptr;
+			self.ptr = self.ptr.add(1);
+
+			Some((offset, byte))
+		}
+	}
+
+	/// Reads a big-endian operand which is `len` bytes wide.
+	#[inline(always)]
+	pub fn read_operand(&mut self, len: usize) -> Option<usize> {
+		if self.remaining() < len {
+			return None;
+		}
+		unsafe {
+			let mut operand = 0;
+			for idx in 0..len {
+				operand = operand << 8 | *self.ptr.add(idx) as usize;
+			}
+			self.ptr = self.ptr.add(len);
+
+			Some(operand)
+		}
+	}
+
+	/// Moves to `offset`, which may be before the current position. Returns
+	/// `None` if it's past the end of the code.
+	#[allow(dead_code)]
+	pub fn jump(&mut self, offset: usize) -> Option<()> {
+		if offset > self.end as usize - self.start as usize {
+			return None;
+		}
+		self.ptr = unsafe { self.start.add(offset) };
+		Some(())
+	}
+}
@@ -1,109 +1,375 @@
-use std::{cell::UnsafeCell, convert::TryFrom};
+//! The bytecode interpreter.
+
+use std::{
+	cell::{Cell, UnsafeCell},
+	convert::TryFrom,
+	fmt, io, mem,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
 
 use crate::{
-	chunk::{self, Chunk, JoinBytes, OpCode},
+	chunk::{Chunk, OpCode, Span},
+	diagnostic::Diagnostic,
 	stack::Stack,
 	value::Value,
 };
 
-use self::debug::Disassembler;
+use self::{debug::Disassembler, ip::Ip};
 
-mod debug;
+pub use self::{
+	debug::TraceFormat,
+	debugger::{Console, Debugger},
+};
 
-// TODO: https://github.com/munificent/craftinginterpreters/blob/6c2ea6f7192910053a78832f0cc34ad56b17ce7c/book/a-virtual-machine.md?plain=1#L50
-lazy_static! {
-	static ref INSTANCE: VM = VM::new();
-}
+mod debug;
+pub mod debugger;
+mod ip;
 
-pub fn get() -> &'static VM {
-	&INSTANCE
-}
+#[cfg(test)]
+mod tests;
 
+/// The result of running a script.
 pub type Result = std::result::Result<(), Error>;
 
-#[derive(Debug)]
+/// Why a script stopped early. Every variant but `Aborted` carries the
+/// `offset` of the instruction it stopped at and the `span` it was generated
+/// from.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
-	Compile,
-	Runtime,
+	/// The byte at `offset` isn't a valid instruction.
+	Compile { offset: usize, span: Span },
+	/// The instruction at `offset` couldn't be executed.
+	Runtime { offset: usize, span: Span },
+	/// The instruction at `offset` needed more values than were on the stack.
+	StackUnderflow { offset: usize, span: Span },
+	/// The instruction at `offset` would have pushed past the stack limit.
+	StackOverflow { offset: usize, span: Span },
+	/// The instruction at `offset` couldn't write to the output sink.
+	Output { offset: usize, span: Span },
+	/// The instruction budget ran out before the instruction at `offset`.
+	OutOfFuel { offset: usize, span: Span },
+	/// The host interrupted execution before the instruction at `offset`.
+	Interrupted { offset: usize, span: Span },
+	/// Execution was stopped from the debugger.
+	Aborted,
+}
+
+impl Error {
+	/// The error code shown in diagnostics, e.g. `E0001`.
+	pub fn code(&self) -> &'static str {
+		match self {
+			Self::Compile { .. } => "E0001",
+			Self::Runtime { .. } => "E0002",
+			Self::Aborted => "E0003",
+			Self::StackUnderflow { .. } => "E0004",
+			Self::Output { .. } => "E0005",
+			Self::OutOfFuel { .. } => "E0006",
+			Self::Interrupted { .. } => "E0007",
+			Self::StackOverflow { .. } => "E0008",
+		}
+	}
+
+	/// A report of the error, pointing at the source of the failed instruction.
+	pub fn to_diagnostic(self) -> Diagnostic {
+		let diagnostic = Diagnostic::error(self.code(), self.to_string());
+		match self {
+			Self::Compile { offset, span }
+			| Self::Runtime { offset, span }
+			| Self::StackUnderflow { offset, span }
+			| Self::StackOverflow { offset, span }
+			| Self::Output { offset, span }
+			| Self::OutOfFuel { offset, span }
+			| Self::Interrupted { offset, span } => diagnostic
+				.with_span(span)
+				.with_note(format!("at bytecode offset {:#06x}", offset), None),
+			Self::Aborted => diagnostic,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Compile { .. } => write!(f, "Invalid instruction"),
+			Self::Runtime { .. } => write!(f, "Malformed bytecode"),
+			Self::Aborted => write!(f, "Execution aborted by the debugger"),
+			Self::StackUnderflow { .. } => write!(f, "Stack underflow"),
+			Self::StackOverflow { .. } => write!(f, "Stack overflow"),
+			Self::Output { .. } => write!(f, "Failed to write output"),
+			Self::OutOfFuel { .. } => write!(f, "Instruction budget exhausted"),
+			Self::Interrupted { .. } => write!(f, "Execution interrupted by the host"),
+		}
+	}
 }
 
+/// There are no call frames yet, so everything runs in the top-level script.
+const FRAME_DEPTH: usize = 1;
+
+/// Runs chunks of bytecode.
+///
+/// A VM keeps its sinks, debugger and stack between runs, and can only be
+/// used from the thread that created it. Only the [interrupt
+/// flag](VM::interrupt_handle) can be shared with other threads.
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<lox_rs::VM>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<lox_rs::VM>();
+/// ```
 pub struct VM {
-	ip: UnsafeCell<Option<chunk::Consumable>>,
 	stack: UnsafeCell<Stack<Value>>,
+	output: UnsafeCell<Box<dyn io::Write>>,
+	fuel: Cell<Option<u64>>,
+	interrupt: Arc<AtomicBool>,
 	disasm: Disassembler,
+	debugger: UnsafeCell<Option<Debugger>>,
+	running: Cell<bool>,
 }
 
-// FIXME: This is definitely not sound
-unsafe impl Send for VM {}
-unsafe impl Sync for VM {}
+/// Marks a VM as running until it's dropped, however the run ends, then
+/// clears the interrupt flag so that it can't carry over to the next run.
+struct Running<'a>(&'a VM);
+
+impl Drop for Running<'_> {
+	fn drop(&mut self) {
+		self.0.interrupt.store(false, Ordering::Relaxed);
+		self.0.running.set(false);
+	}
+}
 
 macro_rules! binop {
-	($self:ident, $stack:ident, $op:tt) => {{
-		let rhs = $stack.pop().ok_or(Error::Runtime)?;
+	($self:ident, $stack:ident, $op:tt, $err:expr) => {{
+		let rhs = $stack.pop().ok_or_else($err)?;
 		$stack.mutate(|lhs| {
 			$self.disasm.write_value(*lhs);
 			$self.disasm.write_value(rhs);
 
 			*lhs = *lhs $op rhs;
-		});
+		})
+		.ok_or_else($err)?;
+	}}
+}
+
+/// Like `binop!`, but takes the right operand from the constant pool, using a
+/// 1-byte handle from the instruction stream.
+macro_rules! binop_const {
+	($self:ident, $stack:ident, $ip:ident, $constants:ident, $op:tt, $err:expr, $underflow:expr) => {{
+		let handle = $ip.read_operand(1).ok_or_else($err)?;
+		let rhs = *$constants.get(handle).ok_or_else($err)?;
+		$stack.mutate(|lhs| {
+			$self.disasm.write_value(*lhs);
+			$self.disasm.write_constant(handle, rhs);
+
+			*lhs = *lhs $op rhs;
+		})
+		.ok_or_else($underflow)?;
 	}}
 }
 
 impl VM {
-	pub fn interpret(&self, chunk: Chunk) -> Result {
-		unsafe {
-			let ip = &mut *self.ip.get();
-			*ip = Some(chunk.into_iter());
-		}
-		self.run()
+	/// The stack limit a new VM starts with.
+	pub const DEFAULT_STACK_LIMIT: usize = Stack::<Value>::DEFAULT_LIMIT;
+
+	/// Starts writing an execution trace to `sink`, one line per instruction.
+	///
+	/// Returns the previously attached sink, if any.
+	pub fn enable_trace<W>(&self, sink: W) -> Option<Box<dyn io::Write>>
+	where W: io::Write + 'static {
+		self.assert_idle();
+		self.disasm.attach(Box::new(sink))
+	}
+
+	/// Sets the shape of the trace output. Defaults to [`TraceFormat::Text`].
+	pub fn set_trace_format(&self, format: TraceFormat) {
+		self.assert_idle();
+		self.disasm.set_format(format);
+	}
+
+	/// Stops tracing and hands back the sink that was attached, if any.
+	pub fn disable_trace(&self) -> Option<Box<dyn io::Write>> {
+		self.assert_idle();
+		self.disasm.detach()
+	}
+
+	/// Sends everything the script prints to `sink`, instead of stdout.
+	///
+	/// Returns the sink that was replaced.
+	pub fn set_output<W>(&self, sink: W) -> Box<dyn io::Write>
+	where W: io::Write + 'static {
+		self.assert_idle();
+		mem::replace(unsafe { &mut *self.output.get() }, Box::new(sink))
+	}
+
+	/// Limits how many values scripts can push onto the stack, after which
+	/// they fail with [`Error::StackOverflow`]. Defaults to
+	/// [`VM::DEFAULT_STACK_LIMIT`].
+	pub fn set_stack_limit(&self, limit: usize) {
+		self.assert_idle();
+		unsafe { &mut *self.stack.get() }.set_limit(limit);
+	}
+
+	/// The current stack limit.
+	pub fn stack_limit(&self) -> usize {
+		self.assert_idle();
+		unsafe { &*self.stack.get() }.limit()
+	}
+
+	/// Limits each call to [`VM::interpret`] to executing `fuel` instructions,
+	/// after which it fails with [`Error::OutOfFuel`]. `None` (the default)
+	/// removes the limit.
+	pub fn set_fuel(&self, fuel: Option<u64>) {
+		self.fuel.set(fuel);
+	}
+
+	/// A flag which another thread can set to stop the running script with
+	/// [`Error::Interrupted`].
+	///
+	/// The flag is cleared when a run ends, however it ends. If it's set while
+	/// no script is running, it stops the next run before its first
+	/// instruction, so a watchdog which fires before [`VM::interpret`] is
+	/// called still cancels the run.
+	pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+		self.interrupt.clone()
 	}
 
-	fn run(&self) -> Result {
+	/// Attaches a debugger, which gets a chance to pause before every
+	/// instruction. Returns the previously attached debugger, if any.
+	pub fn attach_debugger(&self, debugger: Debugger) -> Option<Debugger> {
+		self.assert_idle();
+		unsafe { &mut *self.debugger.get() }.replace(debugger)
+	}
+
+	/// Removes the attached debugger, if any, and returns it.
+	pub fn detach_debugger(&self) -> Option<Debugger> {
+		self.assert_idle();
+		unsafe { &mut *self.debugger.get() }.take()
+	}
+
+	/// Runs `chunk` from the start. The chunk is only borrowed, so it can be
+	/// run again afterwards.
+	///
+	/// Panics if it's called while a script is already running, e.g. from an
+	/// output sink or debugger frontend which holds on to the VM.
+	pub fn interpret(&self, chunk: &Chunk) -> Result {
 		use OpCode::*;
 
-		let (ip, stack) = unsafe { (&mut *self.ip.get(), &mut *self.stack.get()) };
-		assert!(
-			ip.is_some(),
-			"Called vm.run() with an unassigned instruction pointer"
-		);
+		self.assert_idle();
+		self.running.set(true);
+		let _running = Running(self);
+
+		let (stack, output, debugger) = unsafe {
+			(
+				&mut *self.stack.get(),
+				&mut *self.output.get(),
+				&mut *self.debugger.get(),
+			)
+		};
+
+		// Whatever an earlier run left behind mustn't satisfy this one's
+		// operands
+		stack.empty();
+
+		let mut ip = Ip::new(chunk);
+		let constants = chunk.constants();
+		let mut lines = chunk.lines().cursor();
+		let mut fuel = self.fuel.get();
 
-		let ip = ip.as_mut().unwrap();
 		while let Some((offset, byte)) = ip.next() {
-			self.disasm.write_preamble(offset, ip.lines());
+			let span = chunk.lines().seek(&mut lines, offset);
+			let runtime_err = || Error::Runtime { offset, span };
+			let underflow = || Error::StackUnderflow { offset, span };
+			let overflow = |_| Error::StackOverflow { offset, span };
+
+			// There are no jumps or calls yet, so every instruction is checked.
+			// Once there are, only backward jumps and calls need to be.
+			if self.interrupt.load(Ordering::Relaxed) {
+				return Err(Error::Interrupted { offset, span });
+			}
+			if let Some(fuel) = &mut fuel {
+				if *fuel == 0 {
+					return Err(Error::OutOfFuel { offset, span });
+				}
+				*fuel -= 1;
+			}
 
-			let op = OpCode::try_from(byte).map_err(|_| Error::Compile)?;
+			if let Some(debugger) = debugger {
+				if !debugger.before_instr(offset, byte, span, FRAME_DEPTH, stack.as_slice()) {
+					return Err(Error::Aborted);
+				}
+			}
+			self.disasm.write_preamble(offset, span, FRAME_DEPTH);
+
+			let op = OpCode::try_from(byte).map_err(|_| Error::Compile { offset, span })?;
 			self.disasm.write_opcode(op);
 
 			#[rustfmt::skip]
 			#[allow(clippy::assign_op_pattern)]
 			match op {
 				Constant | Constant16 | Constant24 => {
-					let value = match op {
-						Constant => ip.join_bytes(1),
-						Constant16 => ip.join_bytes(2),
-						Constant24 => ip.join_bytes(3),
+					let handle = match op {
+						Constant => ip.read_operand(1),
+						Constant16 => ip.read_operand(2),
+						Constant24 => ip.read_operand(3),
 						_ => unreachable!(),
 					}
-					.and_then(|handle| ip.read_const(handle))
-					.ok_or(Error::Runtime)?;
+					.ok_or_else(runtime_err)?;
+					let value = *constants
+						.get(handle)
+						.ok_or_else(runtime_err)?;
+
+					self.disasm.write_constant(handle, value);
+					stack.try_push(value).map_err(overflow)?;
+				}
+				Zero | One => {
+					let value = if op == Zero { 0. } else { 1. };
 
 					self.disasm.write_value(value);
-					stack.push(value);
+					stack.try_push(value).map_err(overflow)?;
 				}
-				Add      => binop!(self, stack, +),
-				Subtract => binop!(self, stack, -),
-				Multiply => binop!(self, stack, *),
-				Divide   => binop!(self, stack, /),
+				SmallInt | SmallInt16 => {
+					let value = match op {
+						SmallInt => ip.read_operand(1).ok_or_else(runtime_err)? as u8 as i8 as i16,
+						_ => ip.read_operand(2).ok_or_else(runtime_err)? as u16 as i16,
+					};
+
+					self.disasm.write_immediate(value);
+					stack.try_push(value as Value).map_err(overflow)?;
+				}
+				Add      => binop!(self, stack, +, underflow),
+				Subtract => binop!(self, stack, -, underflow),
+				Multiply => binop!(self, stack, *, underflow),
+				Divide   => binop!(self, stack, /, underflow),
+				AddConstant      => binop_const!(self, stack, ip, constants, +, runtime_err, underflow),
+				SubtractConstant => binop_const!(self, stack, ip, constants, -, runtime_err, underflow),
+				MultiplyConstant => binop_const!(self, stack, ip, constants, *, runtime_err, underflow),
+				DivideConstant   => binop_const!(self, stack, ip, constants, /, runtime_err, underflow),
 				Negate => {
 					stack.mutate(|value| {
 						self.disasm.write_value(*value);
 						*value *= -1.;
-					});
+					})
+					.ok_or_else(underflow)?;
+				}
+				Print => {
+					let value = stack.pop().ok_or_else(underflow)?;
+					self.disasm.write_value(value);
+
+					// One write per print, so that sinks see whole lines
+					output
+						.write_all(format!("{}\n", value).as_bytes())
+						.map_err(|_| Error::Output { offset, span })?;
 				}
 				Return => {
-					if let Some(value) = stack.pop() {
-						self.disasm.write_value(value);
-					}
+					let value = stack.pop().ok_or_else(underflow)?;
+					self.disasm.write_value(value);
 				}
 			};
 
@@ -114,11 +380,29 @@ impl VM {
 		Ok(())
 	}
 
-	fn new() -> Self {
+	/// Panics if a script is running. Sinks and debugger frontends can hold on
+	/// to the VM, and nothing the running script has borrowed may be touched
+	/// until it's done.
+	fn assert_idle(&self) {
+		assert!(!self.running.get(), "The VM can't be used while a script is running");
+	}
+
+	/// Creates a VM with an empty stack, and no trace sink or debugger.
+	pub fn new() -> Self {
 		VM {
-			ip: UnsafeCell::new(None),
 			stack: UnsafeCell::new(Stack::new()),
+			output: UnsafeCell::new(Box::new(io::stdout())),
+			fuel: Cell::new(None),
+			interrupt: Arc::new(AtomicBool::new(false)),
 			disasm: Disassembler::new(),
+			debugger: UnsafeCell::new(None),
+			running: Cell::new(false),
 		}
 	}
 }
+
+impl Default for VM {
+	fn default() -> Self {
+		Self::new()
+	}
+}
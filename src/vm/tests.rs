@@ -0,0 +1,861 @@
+use std::{
+	cell::RefCell,
+	io::{self, Cursor},
+	rc::Rc,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
+
+use crate::{
+	chunk::{Chunk, OpCode, OptLevel, Span},
+	stack::Stack,
+};
+
+use super::{
+	debugger::{Breakpoint, Command, Frontend, Pause, PauseReason},
+	Console, Debugger, Error, TraceFormat, VM,
+};
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuf {
+	fn contents(&self) -> String {
+		String::from_utf8(self.0.borrow().clone()).unwrap()
+	}
+}
+
+impl io::Write for SharedBuf {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fn chunk() -> Chunk {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.2, 123);
+	chunk.write_const(3.4, 123);
+	chunk.write_instr(OpCode::Add, 123);
+	chunk.write_instr(OpCode::Negate, 124);
+	chunk.write_instr(OpCode::Return, 124);
+	chunk
+}
+
+#[test]
+fn tracing_is_disabled_by_default() {
+	let vm = VM::new();
+	assert!(!vm.disasm.is_enabled());
+
+	vm.interpret(&chunk()).unwrap();
+	assert!(vm.disable_trace().is_none());
+}
+
+#[test]
+fn it_traces_to_a_sink() {
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.enable_trace(buf.clone());
+
+	vm.interpret(&chunk()).unwrap();
+
+	let expected = r#"
+0x0000   123  0x00 CONSTANT <1.2>            [1.2]
+0x0002     |  0x00 CONSTANT <3.4>            [1.2, 3.4]
+0x0004     |  0x10 ADD <1.2> <3.4>           [4.6]
+0x0005   124  0x14 NEGATE <4.6>              [-4.6]
+0x0006     |  0xff RETURN <-4.6>             []
+"#;
+	assert_eq!(format!("\n{}", buf.contents()), expected);
+}
+
+#[test]
+fn tracing_can_be_turned_off() {
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.enable_trace(buf.clone());
+	assert!(vm.disable_trace().is_some());
+
+	vm.interpret(&chunk()).unwrap();
+	assert!(buf.contents().is_empty());
+}
+
+#[test]
+fn it_traces_json_lines() {
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.set_trace_format(TraceFormat::Json);
+	vm.enable_trace(buf.clone());
+
+	vm.interpret(&chunk()).unwrap();
+
+	let expected = r#"
+{"offset":0,"line":123,"op":"CONSTANT","operands":[0],"constant":1.2,"stack":[1.2],"depth":1}
+{"offset":2,"line":123,"op":"CONSTANT","operands":[1],"constant":3.4,"stack":[1.2,3.4],"depth":1}
+{"offset":4,"line":123,"op":"ADD","operands":[],"constant":null,"stack":[4.6],"depth":1}
+{"offset":5,"line":124,"op":"NEGATE","operands":[],"constant":null,"stack":[-4.6],"depth":1}
+{"offset":6,"line":124,"op":"RETURN","operands":[],"constant":null,"stack":[],"depth":1}
+"#;
+	assert_eq!(format!("\n{}", buf.contents()), expected);
+}
+
+#[test]
+fn json_traces_record_immediate_operands() {
+	let mut chunk = Chunk::new();
+	chunk.write_number(5., 1);
+	chunk.write_number(-300., 1);
+	chunk.write_number(1., 1);
+	chunk.write_instr(OpCode::Return, 1);
+
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.set_trace_format(TraceFormat::Json);
+	vm.enable_trace(buf.clone());
+
+	vm.interpret(&chunk).unwrap();
+
+	let expected = r#"
+{"offset":0,"line":1,"op":"SMALL_INT","operands":[5],"constant":null,"stack":[5],"depth":1}
+{"offset":2,"line":1,"op":"SMALL_INT_16","operands":[-300],"constant":null,"stack":[5,-300],"depth":1}
+{"offset":5,"line":1,"op":"ONE","operands":[],"constant":null,"stack":[5,-300,1],"depth":1}
+{"offset":6,"line":1,"op":"RETURN","operands":[],"constant":null,"stack":[5,-300],"depth":1}
+"#;
+	assert_eq!(format!("\n{}", buf.contents()), expected);
+}
+
+#[test]
+fn json_traces_quote_non_finite_numbers() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(f64::INFINITY, 1);
+	chunk.write_const(f64::NAN, 1);
+	chunk.write_instr(OpCode::Return, 1);
+
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.set_trace_format(TraceFormat::Json);
+	vm.enable_trace(buf.clone());
+
+	vm.interpret(&chunk).unwrap();
+
+	let trace = buf.contents();
+	let second = trace.lines().nth(1).unwrap();
+	assert_eq!(
+		second,
+		r#"{"offset":2,"line":1,"op":"CONSTANT","operands":[1],"constant":"NaN","stack":["inf","NaN"],"depth":1}"#
+	);
+}
+
+/// Records each pause as `(reason, offset, stack size)` and replies with a
+/// canned list of commands.
+struct Scripted {
+	commands: Vec<Command>,
+	pauses: Rc<RefCell<Vec<(PauseReason, usize, usize)>>>,
+}
+
+impl Frontend for Scripted {
+	fn pause(&mut self, pause: &Pause, _: &mut Vec<Breakpoint>) -> Command {
+		self.pauses
+			.borrow_mut()
+			.push((pause.reason, pause.offset, pause.stack.len()));
+
+		if self.commands.is_empty() {
+			Command::Continue
+		} else {
+			self.commands.remove(0)
+		}
+	}
+}
+
+#[test]
+fn debugger_steps_and_stops_at_breakpoints() {
+	let pauses = Rc::new(RefCell::new(vec![]));
+	let mut debugger = Debugger::new(Scripted {
+		commands: vec![Command::StepInto, Command::Continue],
+		pauses: pauses.clone(),
+	});
+	debugger.add_breakpoint(Breakpoint::Offset(6));
+
+	let vm = VM::new();
+	vm.attach_debugger(debugger);
+	vm.interpret(&chunk()).unwrap();
+
+	assert_eq!(
+		&*pauses.borrow(),
+		&[
+			(PauseReason::Entry, 0, 0),
+			(PauseReason::Step, 2, 1),
+			(PauseReason::Breakpoint(Breakpoint::Offset(6)), 6, 1),
+		]
+	);
+}
+
+#[test]
+fn line_breakpoints_only_fire_on_entering_the_line() {
+	let pauses = Rc::new(RefCell::new(vec![]));
+	let mut debugger = Debugger::new(Scripted {
+		commands: vec![],
+		pauses: pauses.clone(),
+	})
+	.stop_on_entry(false);
+	debugger.add_breakpoint(Breakpoint::Line(123));
+	debugger.add_breakpoint(Breakpoint::Line(124));
+
+	let vm = VM::new();
+	vm.attach_debugger(debugger);
+	vm.interpret(&chunk()).unwrap();
+
+	assert_eq!(
+		&*pauses.borrow(),
+		&[
+			(PauseReason::Breakpoint(Breakpoint::Line(123)), 0, 0),
+			(PauseReason::Breakpoint(Breakpoint::Line(124)), 5, 1),
+		]
+	);
+}
+
+#[test]
+fn console_debugger_can_be_scripted() {
+	let input = Cursor::new("b @0x5\nc\nstack\nbl\nd 7\nq\n");
+	let output = SharedBuf::default();
+
+	let vm = VM::new();
+	vm.attach_debugger(Debugger::new(Console::new(input, output.clone())));
+
+	let result = vm.interpret(&chunk());
+	assert!(matches!(result, Err(Error::Aborted)));
+
+	let expected = r#"
+Paused on entry: 0x0000 (line 123)  0x00 CONSTANT
+(lox) Breakpoint set at offset 0x0005
+(lox) Hit breakpoint at offset 0x0005: 0x0005 (line 124)  0x14 NEGATE
+(lox)   0: 4.6
+(lox)   0: offset 0x0005
+(lox) No breakpoint at line 7
+(lox) "#;
+	assert_eq!(format!("\n{}", output.contents()), expected);
+}
+
+#[test]
+fn console_debugger_shows_exact_values() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.00001, 1);
+	chunk.write_instr(OpCode::Return, 1);
+
+	let input = Cursor::new("s\nstack\nq\n");
+	let output = SharedBuf::default();
+
+	let vm = VM::new();
+	vm.attach_debugger(Debugger::new(Console::new(input, output.clone())));
+	vm.interpret(&chunk).unwrap_err();
+
+	assert!(output.contents().contains("(lox)   0: 1.00001\n"), "{}", output.contents());
+}
+
+#[test]
+fn runtime_errors_point_at_the_failing_instruction() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.2, Span::new(1, 0, 3));
+	chunk.write_instr(OpCode::Return, Span::new(1, 0, 3));
+	chunk.write_instr(OpCode::Add, Span::new(1, 4, 5));
+
+	let vm = VM::new();
+	let err = vm.interpret(&chunk).unwrap_err();
+	assert_eq!(
+		err,
+		Error::StackUnderflow {
+			offset: 3,
+			span: Span::new(1, 4, 5),
+		}
+	);
+	assert_eq!(err.to_diagnostic().span, Some(Span::new(1, 4, 5)));
+}
+
+#[test]
+fn every_underflow_is_an_error() {
+	type Program = fn(&mut Chunk);
+	let programs: Vec<(Program, usize)> = vec![
+		(|chunk| chunk.write_instr(OpCode::Negate, 1), 0),
+		(|chunk| chunk.write_instr(OpCode::Return, 1), 0),
+		(|chunk| chunk.write_instr(OpCode::Print, 1), 0),
+		(
+			|chunk| {
+				chunk.write_const(1.5, 1);
+				chunk.write_instr(OpCode::Multiply, 1);
+			},
+			2,
+		),
+		(
+			|chunk| {
+				chunk.write_const(1.5, 1);
+				chunk.write_instr(OpCode::Return, 1);
+				chunk.write_instr(OpCode::Return, 1);
+				chunk.write_instr(OpCode::Return, 1);
+				// Nothing writes superinstructions directly, so patch one in
+				chunk[3..].copy_from_slice(&[OpCode::AddConstant as u8, 0]);
+			},
+			3,
+		),
+	];
+
+	for (program, offset) in programs {
+		let mut chunk = Chunk::new();
+		program(&mut chunk);
+
+		let err = VM::new().interpret(&chunk).unwrap_err();
+		assert_eq!(err, Error::StackUnderflow { offset, span: Span::from(1) });
+		assert_eq!(err.code(), "E0004");
+	}
+}
+
+#[test]
+fn pushing_past_the_stack_limit_is_an_error() {
+	let mut chunk = Chunk::new();
+	for line in 1..=4 {
+		chunk.write_number(1., line);
+	}
+
+	let vm = VM::new();
+	assert_eq!(vm.stack_limit(), VM::DEFAULT_STACK_LIMIT);
+	vm.set_stack_limit(3);
+	let err = vm.interpret(&chunk).unwrap_err();
+	assert_eq!(err, Error::StackOverflow { offset: 3, span: Span::from(4) });
+	assert_eq!(err.code(), "E0008");
+}
+
+#[test]
+fn print_writes_to_the_output_sink() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.5, 1);
+	chunk.write_instr(OpCode::Print, 1);
+	chunk.write_const(-2., 2);
+	chunk.write_instr(OpCode::Print, 2);
+
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.set_output(buf.clone());
+	vm.interpret(&chunk).unwrap();
+	assert_eq!(buf.contents(), "1.5\n-2\n");
+
+	// Swapping the sink back hands over the one that was in use
+	vm.set_output(io::sink());
+	vm.interpret(&chunk).unwrap();
+	assert_eq!(buf.contents(), "1.5\n-2\n");
+}
+
+#[test]
+#[should_panic(expected = "The VM can't be used while a script is running")]
+fn sinks_cant_reconfigure_the_running_vm() {
+	/// Tries to swap itself out mid-write.
+	struct Reentrant(Rc<RefCell<Option<Rc<VM>>>>);
+
+	impl io::Write for Reentrant {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			if let Some(vm) = &*self.0.borrow() {
+				vm.set_output(io::sink());
+			}
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.5, 1);
+	chunk.write_instr(OpCode::Print, 1);
+
+	let vm = Rc::new(VM::new());
+	let slot = Rc::new(RefCell::new(None));
+	vm.set_output(Reentrant(slot.clone()));
+	*slot.borrow_mut() = Some(vm.clone());
+
+	let _ = vm.interpret(&chunk);
+}
+
+#[test]
+fn failing_to_write_output_is_an_error() {
+	struct Closed;
+
+	impl io::Write for Closed {
+		fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+			Err(io::ErrorKind::BrokenPipe.into())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.5, 1);
+	chunk.write_instr(OpCode::Print, 2);
+
+	let vm = VM::new();
+	vm.set_output(Closed);
+	let err = vm.interpret(&chunk).unwrap_err();
+	assert_eq!(err, Error::Output { offset: 2, span: Span::from(2) });
+	assert_eq!(err.code(), "E0005");
+}
+
+#[test]
+fn running_out_of_fuel_is_an_error() {
+	// 5 instructions
+	let chunk = chunk();
+
+	let vm = VM::new();
+	vm.set_fuel(Some(5));
+	assert_eq!(vm.interpret(&chunk), Ok(()));
+
+	// The budget is per run
+	assert_eq!(vm.interpret(&chunk), Ok(()));
+
+	vm.set_fuel(Some(4));
+	let err = vm.interpret(&chunk).unwrap_err();
+	assert_eq!(err, Error::OutOfFuel { offset: 6, span: Span::from(124) });
+	assert_eq!(err.code(), "E0006");
+
+	vm.set_fuel(None);
+	assert_eq!(vm.interpret(&chunk), Ok(()));
+}
+
+#[test]
+fn the_host_can_interrupt_a_script() {
+	/// Sets the interrupt flag from another thread while the VM is paused
+	/// on entry, then lets it carry on.
+	struct Interrupter(Arc<AtomicBool>);
+
+	impl Frontend for Interrupter {
+		fn pause(&mut self, _: &Pause, _: &mut Vec<Breakpoint>) -> Command {
+			let flag = self.0.clone();
+			std::thread::spawn(move || flag.store(true, Ordering::Relaxed))
+				.join()
+				.unwrap();
+			Command::Continue
+		}
+	}
+
+	let vm = VM::new();
+	vm.attach_debugger(Debugger::new(Interrupter(vm.interrupt_handle())));
+
+	// The first instruction runs, and the flag is seen before the second
+	let err = vm.interpret(&chunk()).unwrap_err();
+	assert_eq!(err, Error::Interrupted { offset: 2, span: Span::from(123) });
+	assert_eq!(err.code(), "E0007");
+}
+
+#[test]
+fn interrupts_stop_the_next_run_and_are_then_cleared() {
+	let vm = VM::new();
+	let interrupt = vm.interrupt_handle();
+
+	// Set before the run starts, the flag still stops it
+	interrupt.store(true, Ordering::Relaxed);
+	assert_eq!(
+		vm.interpret(&chunk()),
+		Err(Error::Interrupted { offset: 0, span: Span::from(123) })
+	);
+	assert!(!interrupt.load(Ordering::Relaxed));
+	assert_eq!(vm.interpret(&chunk()), Ok(()));
+
+	/// Sets the interrupt flag, then stops the script before the VM sees it.
+	struct InterruptAndQuit(Arc<AtomicBool>);
+
+	impl Frontend for InterruptAndQuit {
+		fn pause(&mut self, _: &Pause, _: &mut Vec<Breakpoint>) -> Command {
+			self.0.store(true, Ordering::Relaxed);
+			Command::Quit
+		}
+	}
+
+	// Cleared however the run ends, not just when the flag is seen
+	vm.attach_debugger(Debugger::new(InterruptAndQuit(interrupt.clone())));
+	assert_eq!(vm.interpret(&chunk()), Err(Error::Aborted));
+	assert!(!interrupt.load(Ordering::Relaxed));
+	vm.detach_debugger();
+	assert_eq!(vm.interpret(&chunk()), Ok(()));
+}
+
+#[test]
+fn aborted_runs_leave_nothing_for_the_next_one() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.5, 1);
+	chunk.write_const(2.5, 1);
+	chunk.write_instr(OpCode::Return, 1);
+
+	let vm = VM::new();
+	vm.set_fuel(Some(2));
+	assert!(matches!(vm.interpret(&chunk), Err(Error::OutOfFuel { .. })));
+
+	let mut next = Chunk::new();
+	next.write_instr(OpCode::Add, 2);
+	next.write_instr(OpCode::Print, 2);
+	assert_eq!(
+		vm.interpret(&next),
+		Err(Error::StackUnderflow { offset: 0, span: Span::from(2) })
+	);
+}
+
+#[test]
+fn underflow_is_detected_after_a_run_which_leaves_values_behind() {
+	let mut leftovers = Chunk::new();
+	leftovers.write_const(3., 1);
+	leftovers.write_const(4., 1);
+
+	let mut chunk = Chunk::new();
+	chunk.write_instr(OpCode::Negate, 2);
+
+	let vm = VM::new();
+	assert_eq!(vm.interpret(&leftovers), Ok(()));
+	assert_eq!(
+		vm.interpret(&chunk),
+		Err(Error::StackUnderflow { offset: 0, span: Span::from(2) })
+	);
+}
+
+/// Runs `chunk` and collects the bits of the values popped by `Print` and
+/// `Return`, so that results which only differ past the precision of the
+/// trace, or in the sign of a zero, still compare unequal.
+fn returned_values(chunk: Chunk) -> Vec<u64> {
+	try_returned_values(&chunk).expect("The chunk failed to run")
+}
+
+#[test]
+fn optimizing_never_hides_an_underflow() {
+	let programs: Vec<fn(&mut Chunk)> = vec![
+		|chunk| {
+			chunk.write_instr(OpCode::One, 1);
+			chunk.write_instr(OpCode::Multiply, 1);
+		},
+		|chunk| {
+			chunk.write_instr(OpCode::Negate, 1);
+			chunk.write_instr(OpCode::Negate, 1);
+		},
+		|chunk| {
+			chunk.write_const(2., 1);
+			chunk.write_instr(OpCode::Return, 1);
+			chunk.write_const(-0., 2);
+			chunk.write_instr(OpCode::Add, 2);
+		},
+	];
+
+	for program in programs {
+		let mut chunk = Chunk::new();
+		program(&mut chunk);
+		let chunk = chunk.optimize(OptLevel::Peephole);
+
+		let err = VM::new().interpret(&chunk).unwrap_err();
+		assert!(matches!(err, Error::StackUnderflow { .. }), "{:?}", chunk);
+	}
+}
+
+#[test]
+fn optimized_chunks_return_the_same_values() {
+	let programs: Vec<fn(&mut Chunk)> = vec![
+		|chunk| {
+			chunk.write_const(1.2, 1);
+			chunk.write_const(3.4, 1);
+			chunk.write_instr(OpCode::Add, 1);
+			chunk.write_const(5.6, 1);
+			chunk.write_instr(OpCode::Divide, 1);
+			chunk.write_instr(OpCode::Negate, 1);
+			chunk.write_instr(OpCode::Return, 1);
+		},
+		|chunk| {
+			chunk.write_const(0., 1);
+			chunk.write_instr(OpCode::Negate, 1);
+			chunk.write_instr(OpCode::Negate, 1);
+			chunk.write_instr(OpCode::Negate, 1);
+			chunk.write_const(0., 1);
+			chunk.write_instr(OpCode::Add, 1);
+			chunk.write_instr(OpCode::Return, 1);
+		},
+		|chunk| {
+			chunk.write_const(7., 1);
+			chunk.write_const(2., 1);
+			chunk.write_instr(OpCode::Return, 1);
+			chunk.write_const(1., 2);
+			chunk.write_instr(OpCode::Multiply, 2);
+			chunk.write_const(-0., 2);
+			chunk.write_instr(OpCode::Add, 2);
+			chunk.write_instr(OpCode::Return, 2);
+		},
+		|chunk| {
+			chunk.write_const(7., 1);
+			chunk.write_const(2., 1);
+			chunk.write_instr(OpCode::Return, 1);
+			chunk.write_const(3., 2);
+			chunk.write_instr(OpCode::Subtract, 2);
+			chunk.write_const(0., 2);
+			chunk.write_instr(OpCode::Add, 2);
+			chunk.write_const(4., 2);
+			chunk.write_instr(OpCode::Divide, 2);
+			chunk.write_const(0.5, 2);
+			chunk.write_instr(OpCode::Multiply, 2);
+			chunk.write_instr(OpCode::Return, 2);
+		},
+		|chunk| {
+			chunk.write_const(-200., 1);
+			chunk.write_const(300., 1);
+			chunk.write_const(-1., 1);
+			chunk.write_instr(OpCode::Return, 1);
+			chunk.write_instr(OpCode::Return, 1);
+			chunk.write_instr(OpCode::Return, 1);
+		},
+		|chunk| {
+			chunk.write_const(1., 1);
+			chunk.write_const(0., 1);
+			chunk.write_instr(OpCode::Divide, 1);
+			chunk.write_const(f64::INFINITY, 1);
+			chunk.write_instr(OpCode::Subtract, 1);
+			chunk.write_instr(OpCode::Return, 1);
+		},
+	];
+
+	for program in programs {
+		let mut chunk = Chunk::new();
+		program(&mut chunk);
+		let expected = returned_values(chunk);
+		assert!(!expected.is_empty());
+
+		let mut chunk = Chunk::new();
+		program(&mut chunk);
+		let optimized = chunk.optimize(OptLevel::Peephole);
+		assert_eq!(returned_values(optimized), expected);
+	}
+}
+
+#[test]
+fn chunks_can_be_run_more_than_once() {
+	let vm = VM::new();
+	let buf = SharedBuf::default();
+	vm.enable_trace(buf.clone());
+
+	let chunk = chunk();
+	vm.interpret(&chunk).unwrap();
+	let first = buf.contents();
+	vm.interpret(&chunk).unwrap();
+
+	assert_eq!(buf.contents(), first.repeat(2));
+}
+
+#[test]
+fn ip_reads_operands_and_jumps() {
+	use super::ip::Ip;
+
+	let code = [0x01, 0x02, 0x03, 0x04];
+	let mut ip = Ip::new(&code);
+	assert_eq!(ip.next(), Some((0, 0x01)));
+	assert_eq!(ip.read_operand(2), Some(0x0203));
+	assert_eq!(ip.read_operand(2), None);
+	assert_eq!(ip.offset(), 3);
+
+	// Backwards
+	assert_eq!(ip.jump(1), Some(()));
+	assert_eq!(ip.next(), Some((1, 0x02)));
+	assert_eq!(ip.jump(5), None);
+	assert_eq!(ip.jump(4), Some(()));
+	assert_eq!(ip.next(), None);
+}
+
+#[test]
+fn truncated_operands_are_runtime_errors() {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.5, 1);
+	chunk.write_instr(OpCode::Constant16, 2);
+	chunk.write_instr(OpCode::Return, 2);
+
+	let vm = VM::new();
+	let err = vm.interpret(&chunk).unwrap_err();
+	assert_eq!(
+		err,
+		Error::Runtime {
+			offset: 2,
+			span: Span::from(2),
+		}
+	);
+}
+
+/// A small xorshift generator, so that failures can be reproduced from the
+/// seed.
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	fn below(&mut self, n: u64) -> u64 {
+		self.next() % n
+	}
+}
+
+/// Builds a chunk of mostly-valid random bytecode, with a few constants in
+/// its pool.
+fn random_chunk(rng: &mut Rng) -> Chunk {
+	const OPS: &[OpCode] = &[
+		OpCode::Constant,
+		OpCode::Constant16,
+		OpCode::Constant24,
+		OpCode::Zero,
+		OpCode::One,
+		OpCode::SmallInt,
+		OpCode::SmallInt16,
+		OpCode::Add,
+		OpCode::Subtract,
+		OpCode::Multiply,
+		OpCode::Divide,
+		OpCode::Negate,
+		OpCode::AddConstant,
+		OpCode::SubtractConstant,
+		OpCode::MultiplyConstant,
+		OpCode::DivideConstant,
+		OpCode::Print,
+		OpCode::Return,
+	];
+	const VALUES: &[f64] = &[0., -0., 1., -1., 0.5, 1e300, f64::NAN, f64::INFINITY];
+
+	let mut chunk = Chunk::new();
+	let pool_len = rng.below(6);
+	for _ in 0..pool_len {
+		let value = VALUES[rng.below(VALUES.len() as u64) as usize];
+		chunk.write_const(value, 1);
+	}
+
+	// Mostly short, so that most chunks run to completion, but sometimes
+	// long enough to overflow the stack limits the fuzz test runs with
+	let max_len = match rng.below(4) {
+		0 => Stack::<f64>::INITIAL_CAPACITY * 2,
+		_ => 32,
+	};
+	let len = rng.below(max_len as u64) as usize;
+	let mut code = vec![];
+	while code.len() < len {
+		// Every so often, throw in a byte which is probably garbage
+		if rng.below(50) == 0 {
+			code.push(rng.next() as u8);
+			continue;
+		}
+
+		// The first 7 ops push a value, so favor them to keep the stack from
+		// underflowing straight away
+		let choices = if rng.below(2) == 0 { 7 } else { OPS.len() };
+		let op = OPS[rng.below(choices as u64) as usize];
+		code.push(op as u8);
+		let operand_len = match op {
+			OpCode::Constant16 | OpCode::SmallInt16 => 2,
+			OpCode::Constant24 => 3,
+			OpCode::Constant
+			| OpCode::SmallInt
+			| OpCode::AddConstant
+			| OpCode::SubtractConstant
+			| OpCode::MultiplyConstant
+			| OpCode::DivideConstant => 1,
+			_ => 0,
+		};
+		// Mostly valid constant handles, big-endian
+		for idx in 0..operand_len {
+			code.push(match idx + 1 == operand_len {
+				true if pool_len > 0 && rng.below(10) > 0 => rng.below(pool_len) as u8,
+				true => rng.below(8) as u8,
+				false => (rng.below(50) == 0) as u8,
+			});
+		}
+	}
+
+	let mut line = 1;
+	while chunk.len() < code.len() {
+		line += rng.below(3) as usize;
+		chunk.write_instr(OpCode::Return, line);
+	}
+	code.resize(chunk.len(), OpCode::Return as u8);
+	chunk.copy_from_slice(&code);
+	chunk
+}
+
+/// Like `returned_values`, but returns the error if the chunk fails.
+fn try_returned_values(chunk: &Chunk) -> Result<Vec<u64>, Error> {
+	try_returned_values_with_limit(chunk, VM::DEFAULT_STACK_LIMIT)
+}
+
+/// Like `try_returned_values`, but with the VM's stack limited to
+/// `stack_limit` values.
+fn try_returned_values_with_limit(
+	chunk: &Chunk,
+	stack_limit: usize,
+) -> Result<Vec<u64>, Error> {
+	let values = Rc::new(RefCell::new(vec![]));
+	let vm = VM::new();
+	vm.set_stack_limit(stack_limit);
+	vm.set_output(io::sink());
+	vm.attach_debugger(Debugger::new(Popped(values.clone())));
+	vm.interpret(chunk)?;
+
+	let values = values.borrow().clone();
+	Ok(values)
+}
+
+/// Steps through every instruction, and records the bits of the value on top
+/// of the stack before each `Print` and `Return`.
+struct Popped(Rc<RefCell<Vec<u64>>>);
+
+impl Frontend for Popped {
+	fn pause(&mut self, pause: &Pause, _: &mut Vec<Breakpoint>) -> Command {
+		let pops = pause.byte == OpCode::Print as u8 || pause.byte == OpCode::Return as u8;
+		if let (true, Some(value)) = (pops, pause.stack.last()) {
+			self.0.borrow_mut().push(value.to_bits());
+		}
+		Command::StepInto
+	}
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // Too slow under Miri
+fn overflowing_the_default_stack_limit_is_an_error() {
+	let mut chunk = Chunk::new();
+	for _ in 0..70_000 {
+		chunk.write_instr(OpCode::One, 1);
+	}
+
+	let err = VM::new().interpret(&chunk).unwrap_err();
+	assert!(matches!(err, Error::StackOverflow { offset, .. } if offset == VM::DEFAULT_STACK_LIMIT));
+}
+
+/// A quick check over fixed seeds. For longer runs over arbitrary input, use
+/// the `interpret` target in `fuzz/` with `cargo fuzz run interpret`.
+#[test]
+#[cfg_attr(miri, ignore)] // Too slow under Miri
+fn random_bytecode_never_panics() {
+	for seed in 1..=2000 {
+		let mut rng = Rng(seed);
+		let chunk = random_chunk(&mut rng);
+		let stack_limit = 1 + rng.below(32) as usize;
+
+		let result = std::panic::catch_unwind(|| {
+			let _ = format!("{:?}", chunk);
+			let expected = try_returned_values_with_limit(&chunk, stack_limit);
+
+			let optimized = random_chunk(&mut Rng(seed)).optimize(OptLevel::Peephole);
+			let _ = format!("{:?}", optimized);
+			let actual = try_returned_values_with_limit(&optimized, stack_limit);
+
+			// The optimizer can fold away pushes which would have overflowed
+			// the stack, but it mustn't change the result of a chunk which
+			// succeeds, or let any other failure succeed
+			match expected {
+				Err(Error::StackOverflow { .. }) => {}
+				Err(_) => assert!(actual.is_err(), "{:?}\n\n{:?}", chunk, optimized),
+				Ok(_) => assert_eq!(actual, expected, "{:?}\n\n{:?}", chunk, optimized),
+			}
+		});
+		assert!(result.is_ok(), "Panicked with seed {}", seed);
+	}
+}
@@ -0,0 +1,306 @@
+use std::{
+	cell::RefCell,
+	collections::VecDeque,
+	io::{self, BufRead, BufReader, Cursor, Write},
+	process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+	rc::Rc,
+};
+
+use lox_rs::{Chunk, OpCode};
+use serde_json::{json, Value};
+
+/// A scripted DAP client talking to `lox dap` over stdio.
+struct Client {
+	child: Child,
+	stdin: ChildStdin,
+	stdout: BufReader<ChildStdout>,
+	seq: i64,
+	events: VecDeque<Value>,
+}
+
+impl Client {
+	fn spawn() -> Self {
+		let mut child = Command::new(env!("CARGO_BIN_EXE_lox_rs"))
+			.arg("dap")
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.spawn()
+			.unwrap();
+
+		Self {
+			stdin: child.stdin.take().unwrap(),
+			stdout: BufReader::new(child.stdout.take().unwrap()),
+			child,
+			seq: 0,
+			events: VecDeque::new(),
+		}
+	}
+
+	fn request(&mut self, command: &str, arguments: Value) -> Value {
+		self.seq += 1;
+		let message = frame(self.seq, command, arguments);
+		self.stdin.write_all(message.as_bytes()).unwrap();
+		self.stdin.flush().unwrap();
+
+		loop {
+			let message = self.read();
+			if message["type"] == "event" {
+				self.events.push_back(message);
+			} else {
+				assert_eq!(message["request_seq"], self.seq);
+				return message;
+			}
+		}
+	}
+
+	fn event(&mut self, name: &str) -> Value {
+		loop {
+			let message = match self.events.pop_front() {
+				Some(message) => message,
+				None => self.read(),
+			};
+			if message["type"] == "event" && message["event"] == name {
+				return message["body"].clone();
+			}
+		}
+	}
+
+	fn read(&mut self) -> Value {
+		read_message(&mut self.stdout).expect("Debug adapter closed its output")
+	}
+}
+
+/// A request with its `Content-Length` header.
+fn frame(seq: i64, command: &str, arguments: Value) -> String {
+	let body = json!({
+		"seq": seq,
+		"type": "request",
+		"command": command,
+		"arguments": arguments,
+	})
+	.to_string();
+	format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+/// Reads the next message, or `None` at the end of `input`.
+fn read_message<R: BufRead>(input: &mut R) -> Option<Value> {
+	let mut len = 0;
+	loop {
+		let mut header = String::new();
+		if input.read_line(&mut header).unwrap() == 0 {
+			return None;
+		}
+		let header = header.trim();
+		if header.is_empty() {
+			break;
+		}
+		if let Some(value) = header.strip_prefix("Content-Length:") {
+			len = value.trim().parse().unwrap();
+		}
+	}
+
+	let mut body = vec![0; len];
+	input.read_exact(&mut body).unwrap();
+	Some(serde_json::from_slice(&body).unwrap())
+}
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Runs the adapter in-process on `input`, with `load` standing in for the
+/// file system, and returns everything it sent back.
+fn serve<L>(input: Vec<u8>, load: L) -> Vec<Value>
+where L: FnMut(&str) -> Result<Chunk, String> {
+	let output = SharedBuf::default();
+	lox_rs::serve_debug_adapter(Cursor::new(input), output.clone(), load).unwrap();
+
+	let output = output.0.borrow().clone();
+	let mut output = Cursor::new(output);
+	let mut messages = vec![];
+	while let Some(message) = read_message(&mut output) {
+		messages.push(message);
+	}
+	messages
+}
+
+/// Frames `requests` one after another, as a client would send them.
+fn script(requests: &[(&str, Value)]) -> Vec<u8> {
+	let mut input = String::new();
+	for (seq, (command, arguments)) in requests.iter().enumerate() {
+		input += &frame(seq as i64 + 1, command, arguments.clone());
+	}
+	input.into_bytes()
+}
+
+/// The response to the request with sequence number `seq`.
+fn response(messages: &[Value], seq: i64) -> &Value {
+	messages
+		.iter()
+		.find(|message| message["type"] == "response" && message["request_seq"] == seq)
+		.unwrap_or_else(|| panic!("No response to request {}", seq))
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // Miri can't spawn processes
+fn dap_session() {
+	let mut client = Client::spawn();
+
+	let init = client.request("initialize", json!({ "adapterID": "lox" }));
+	assert_eq!(init["success"], true);
+	client.event("initialized");
+
+	client.request("launch", json!({ "program": "demo.lox", "stopOnEntry": true }));
+	let bps = client.request(
+		"setBreakpoints",
+		json!({ "source": { "path": "other.lox" }, "breakpoints": [{ "line": 124 }] }),
+	);
+	assert_eq!(bps["body"]["breakpoints"][0]["verified"], false);
+	let bps = client.request(
+		"setBreakpoints",
+		json!({
+			"source": { "path": "demo.lox" },
+			"breakpoints": [{ "line": 125 }, { "line": 200 }],
+		}),
+	);
+	assert_eq!(bps["body"]["breakpoints"][0]["verified"], true);
+	assert_eq!(bps["body"]["breakpoints"][1]["verified"], false);
+	client.request("configurationDone", json!({}));
+
+	assert_eq!(client.event("stopped")["reason"], "entry");
+
+	let threads = client.request("threads", json!({}));
+	assert_eq!(threads["body"]["threads"][0]["id"], 1);
+
+	let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+	let frame = &trace["body"]["stackFrames"][0];
+	assert_eq!(frame["line"], 123);
+	assert_eq!(frame["source"]["path"], "demo.lox");
+
+	client.request("continue", json!({ "threadId": 1 }));
+	assert_eq!(client.event("stopped")["reason"], "breakpoint");
+
+	client.request("next", json!({ "threadId": 1 }));
+	assert_eq!(client.event("stopped")["reason"], "step");
+	client.request("stepIn", json!({ "threadId": 1 }));
+	assert_eq!(client.event("stopped")["reason"], "step");
+
+	let scopes = client.request("scopes", json!({ "frameId": 1 }));
+	let stack_ref = scopes["body"]["scopes"][0]["variablesReference"].clone();
+	let vars = client.request("variables", json!({ "variablesReference": stack_ref }));
+	assert_eq!(
+		vars["body"]["variables"],
+		json!([
+			{ "name": "[1]", "value": "3.4", "variablesReference": 0 },
+			{ "name": "[0]", "value": "1.2", "variablesReference": 0 },
+		])
+	);
+
+	// There's no caller to step out to, so this runs the script to completion
+	client.request("stepOut", json!({ "threadId": 1 }));
+	assert_eq!(client.event("exited")["exitCode"], 0);
+	client.event("terminated");
+
+	let disconnect = client.request("disconnect", json!({}));
+	assert_eq!(disconnect["success"], true);
+	assert!(client.child.wait().unwrap().success());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // Miri can't spawn processes
+fn dap_rejects_inspection_while_running() {
+	let mut client = Client::spawn();
+	client.request("initialize", json!({}));
+
+	let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+	assert_eq!(trace["success"], false);
+
+	client.request("disconnect", json!({}));
+	assert!(client.child.wait().unwrap().success());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // Miri can't spawn processes
+fn dap_verifies_breakpoints_set_before_launch() {
+	let mut client = Client::spawn();
+	client.request("initialize", json!({}));
+
+	let bps = client.request(
+		"setBreakpoints",
+		json!({ "source": { "path": "demo.lox" }, "breakpoints": [{ "line": 124 }, { "line": 7 }] }),
+	);
+	assert_eq!(bps["body"]["breakpoints"][0]["verified"], false);
+
+	client.request("launch", json!({ "program": "demo.lox" }));
+	let changed = client.event("breakpoint");
+	assert_eq!(changed["breakpoint"]["line"], 124);
+	assert_eq!(changed["breakpoint"]["verified"], true);
+	let changed = client.event("breakpoint");
+	assert_eq!(changed["breakpoint"]["line"], 7);
+	assert_eq!(changed["breakpoint"]["verified"], false);
+
+	client.request("configurationDone", json!({}));
+	assert_eq!(client.event("stopped")["reason"], "breakpoint");
+	let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+	assert_eq!(trace["body"]["stackFrames"][0]["line"], 124);
+
+	client.request("disconnect", json!({}));
+	assert!(client.child.wait().unwrap().success());
+}
+
+#[test]
+fn dap_shows_exact_values() {
+	let input = script(&[
+		("initialize", json!({})),
+		("launch", json!({ "program": "exact.lox", "stopOnEntry": true })),
+		("configurationDone", json!({})),
+		("stepIn", json!({ "threadId": 1 })),
+		("scopes", json!({ "frameId": 1 })),
+		("variables", json!({ "variablesReference": 1 })),
+		("disconnect", json!({})),
+	]);
+	let messages = serve(input, |_| {
+		let mut chunk = Chunk::new();
+		chunk.write_const(1.00001, 1);
+		chunk.write_instr(OpCode::Return, 1);
+		Ok(chunk)
+	});
+
+	assert_eq!(response(&messages, 5)["body"]["scopes"][0]["variablesReference"], 1);
+	assert_eq!(
+		response(&messages, 6)["body"]["variables"],
+		json!([{ "name": "[0]", "value": "1.00001", "variablesReference": 0 }])
+	);
+}
+
+#[test]
+fn dap_fails_launches_which_cant_load() {
+	let input = script(&[
+		("initialize", json!({})),
+		("launch", json!({ "program": "missing.lox" })),
+		("disconnect", json!({})),
+	]);
+	let messages = serve(input, |program| Err(format!("Couldn't read {}", program)));
+
+	let launch = response(&messages, 2);
+	assert_eq!(launch["success"], false);
+	assert_eq!(launch["message"], "Couldn't read missing.lox");
+	assert!(!messages.iter().any(|message| message["event"] == "terminated"));
+}
+
+#[test]
+fn dap_rejects_oversized_messages() {
+	let input = b"Content-Length: 18446744073709551615\r\n\r\n{}".to_vec();
+	let err = lox_rs::serve_debug_adapter(Cursor::new(input), io::sink(), |_| Ok(Chunk::new()))
+		.unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
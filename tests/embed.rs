@@ -0,0 +1,36 @@
+use lox_rs::{Chunk, Error, OpCode, OptLevel, Span, VM};
+
+fn script() -> Chunk {
+	let mut chunk = Chunk::new();
+	chunk.write_const(1.2, 1);
+	chunk.write_const(3.4, 1);
+	chunk.write_instr(OpCode::Add, 1);
+	chunk.write_instr(OpCode::Negate, 1);
+	chunk.write_instr(OpCode::Return, 1);
+	chunk
+}
+
+#[test]
+fn embedders_can_run_chunks_on_their_own_vm() {
+	let vm = VM::new();
+	let chunk = script();
+
+	assert_eq!(chunk.constants(), &[1.2, 3.4]);
+	assert_eq!(chunk.lines().find_line(0), 1);
+
+	assert_eq!(vm.interpret(&chunk), Ok(()));
+	assert_eq!(vm.interpret(&chunk.optimize(OptLevel::Peephole)), Ok(()));
+	assert_eq!(lox_rs::interpret(&script()), Ok(()));
+}
+
+#[test]
+fn embedders_get_errors_back() {
+	let mut chunk = Chunk::new();
+	chunk.write_instr(OpCode::Negate, 7);
+
+	let err = VM::new().interpret(&chunk).unwrap_err();
+	assert_eq!(err, Error::StackUnderflow { offset: 0, span: Span::from(7) });
+
+	let diagnostic = err.to_diagnostic();
+	assert_eq!(diagnostic.span, Some(Span::from(7)));
+}